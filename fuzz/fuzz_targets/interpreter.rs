@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Profile byte, key script and ROM, see cpu_emulator::fuzz
+fuzz_target!(|data: &[u8]| {
+    cpu_emulator::fuzz::run(data);
+});
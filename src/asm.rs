@@ -0,0 +1,1174 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::symbols::{self, SymbolMap};
+
+const ADDR_OFFSET: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+/// An assembled ROM. `rom` starts at 0x200.
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Program {
+    pub fn symbols(&self) -> SymbolMap {
+        symbols::parse(&self.symbol_map())
+    }
+
+    /// Symbol map in the format read by `symbols::parse`.
+    pub fn symbol_map(&self) -> String {
+        symbols::format(&self.labels)
+    }
+}
+
+/// Assembles Octo source into a ROM image.
+pub fn assemble(source: &str) -> Result<Program> {
+    let mut asm = Assembler::new(tokenize(source));
+    asm.run()?;
+    asm.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+
+    tokens
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(u8),
+    Imm(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Cond {
+    Eq(u8, Operand),
+    Ne(u8, Operand),
+    Lt(u8, Operand),
+    Gt(u8, Operand),
+    Le(u8, Operand),
+    Ge(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Cond {
+    fn negate(self) -> Cond {
+        match self {
+            Cond::Eq(x, rhs) => Cond::Ne(x, rhs),
+            Cond::Ne(x, rhs) => Cond::Eq(x, rhs),
+            Cond::Lt(x, rhs) => Cond::Ge(x, rhs),
+            Cond::Ge(x, rhs) => Cond::Lt(x, rhs),
+            Cond::Gt(x, rhs) => Cond::Le(x, rhs),
+            Cond::Le(x, rhs) => Cond::Gt(x, rhs),
+            Cond::Key(x) => Cond::NotKey(x),
+            Cond::NotKey(x) => Cond::Key(x),
+        }
+    }
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Fixup {
+    addr: usize,
+    label: String,
+    line: usize,
+    long: bool,
+}
+
+enum Flow {
+    Begin {
+        jump: usize,
+        line: usize,
+    },
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        line: usize,
+    },
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    pc: usize,
+    end: usize,
+    line: usize,
+    labels: BTreeMap<String, u16>,
+    consts: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        Self {
+            tokens,
+            memory: vec![0; MEMORY_SIZE],
+            pc: ADDR_OFFSET,
+            end: ADDR_OFFSET,
+            line: 1,
+            labels: BTreeMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            flow: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(AsmError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some(flow) = self.flow.last() {
+            let (kind, line) = match flow {
+                Flow::Begin { line, .. } | Flow::Else { line, .. } => ("begin", *line),
+                Flow::Loop { line, .. } => ("loop", *line),
+            };
+            return Err(AsmError {
+                line,
+                message: format!("unterminated '{}'", kind),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Program> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = match self.labels.get(&fixup.label) {
+                Some(addr) => *addr,
+                None => return self.error(format!("undefined label '{}'", fixup.label)),
+            };
+
+            if fixup.long {
+                self.memory[fixup.addr] = (addr >> 8) as u8;
+                self.memory[fixup.addr + 1] = (addr & 0xff) as u8;
+            } else {
+                self.patch_nnn(fixup.addr, addr)?;
+            }
+        }
+
+        Ok(Program {
+            rom: self.memory[ADDR_OFFSET..self.end.max(ADDR_OFFSET)].to_vec(),
+            labels: self.labels,
+        })
+    }
+
+    // Output
+
+    fn emit_byte(&mut self, byte: u8) -> Result<()> {
+        if self.pc >= MEMORY_SIZE {
+            return self.error("program does not fit in memory");
+        }
+
+        self.memory[self.pc] = byte;
+        self.pc += 1;
+        self.end = self.end.max(self.pc);
+        Ok(())
+    }
+
+    fn emit(&mut self, instr: Instruction) -> Result<()> {
+        let opcode = instr.encode();
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte((opcode & 0xff) as u8)
+    }
+
+    fn patch_nnn(&mut self, addr: usize, target: u16) -> Result<()> {
+        if target > 0xfff {
+            return self.error(format!("address {:#x} does not fit in 12 bits", target));
+        }
+
+        self.memory[addr] = (self.memory[addr] & 0xf0) | (target >> 8) as u8;
+        self.memory[addr + 1] = (target & 0xff) as u8;
+        Ok(())
+    }
+
+    /// Emits a `?NNN` instruction whose address may be a forward reference.
+    fn emit_addr(&mut self, make: fn(u16) -> Instruction, token: &str) -> Result<()> {
+        let addr = self.pc;
+        match self.resolve(token)? {
+            Some(target) => {
+                let target = self.address(target)?;
+                self.emit(make(target))
+            }
+            None => {
+                self.fixups.push(Fixup {
+                    addr,
+                    label: token.to_string(),
+                    line: self.line,
+                    long: false,
+                });
+                self.emit(make(0))
+            }
+        }
+    }
+
+    /// Emits a jump with a placeholder target and returns its address.
+    fn emit_placeholder_jump(&mut self) -> Result<usize> {
+        let addr = self.pc;
+        self.emit(Instruction::Jump { nnn: 0 })?;
+        Ok(addr)
+    }
+
+    fn here(&self) -> Result<u16> {
+        match u16::try_from(self.pc) {
+            Ok(pc) => Ok(pc),
+            Err(_) => self.error("program does not fit in memory"),
+        }
+    }
+
+    // Operands
+
+    /// Value of a number, constant or already defined label. `Ok(None)` means
+    /// the token is a name that may still be defined as a label later on.
+    fn resolve(&self, token: &str) -> Result<Option<i64>> {
+        if let Some(value) = parse_number(token) {
+            return Ok(Some(value));
+        }
+        if let Some(value) = self.consts.get(token) {
+            return Ok(Some(*value));
+        }
+        if let Some(addr) = self.labels.get(token) {
+            return Ok(Some(i64::from(*addr)));
+        }
+        if self.is_register(token) || !is_identifier(token) {
+            return self.error(format!("expected a value, found '{}'", token));
+        }
+        Ok(None)
+    }
+
+    fn value(&self, token: &str) -> Result<i64> {
+        match self.resolve(token)? {
+            Some(value) => Ok(value),
+            None => self.error(format!("undefined name '{}'", token)),
+        }
+    }
+
+    fn byte(&self, value: i64) -> Result<u8> {
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("value {} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&self, value: i64) -> Result<u8> {
+        if !(0..=15).contains(&value) {
+            return self.error(format!("value {} does not fit in a nibble", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn address(&self, value: i64) -> Result<u16> {
+        if !(0..=0xfff).contains(&value) {
+            return self.error(format!("address {:#x} does not fit in 12 bits", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        parse_register(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    fn register(&self, token: &str) -> Result<u8> {
+        if let Some(reg) = parse_register(token) {
+            return Ok(reg);
+        }
+        match self.aliases.get(token) {
+            Some(reg) => Ok(*reg),
+            None => self.error(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    fn next_register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.register(&token)
+    }
+
+    fn next_byte(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        self.byte(value)
+    }
+
+    fn next_nibble(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        self.nibble(value)
+    }
+
+    /// A value, or a `{ expression }` block.
+    fn next_value(&mut self) -> Result<i64> {
+        if self.peek() == Some("{") {
+            self.next()?;
+            return self.calc_block();
+        }
+        let token = self.next()?;
+        self.value(&token)
+    }
+
+    fn new_name(&mut self) -> Result<String> {
+        let name = self.next()?;
+        if !is_identifier(&name) || self.is_register(&name) {
+            return self.error(format!("invalid name '{}'", name));
+        }
+        Ok(name)
+    }
+
+    // Statements
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+
+        match token.as_str() {
+            ":" => {
+                let name = self.new_name()?;
+                if self.labels.contains_key(&name) {
+                    return self.error(format!("label '{}' is already defined", name));
+                }
+                let here = self.here()?;
+                self.labels.insert(name, here);
+            }
+            ":const" => {
+                let name = self.new_name()?;
+                let value = self.next_value()?;
+                self.consts.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.new_name()?;
+                let reg = self.next_register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.new_name()?;
+                self.expect("{")?;
+                let value = self.calc_block()?;
+                self.consts.insert(name, value);
+            }
+            ":byte" => {
+                let value = self.next_value()?;
+                let byte = self.byte(value)?;
+                self.emit_byte(byte)?;
+            }
+            ":org" => {
+                let value = self.next_value()?;
+                if !(0..MEMORY_SIZE as i64).contains(&value) {
+                    return self.error(format!("address {:#x} is outside memory", value));
+                }
+                // The ROM starts at 0x200, anything before it would be lost
+                if value < ADDR_OFFSET as i64 {
+                    return self.error(format!("address {:#x} is before the ROM at 0x200", value));
+                }
+                self.pc = value as usize;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_addr(|nnn| Instruction::Call { nnn }, &target)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ";" | "return" => self.emit(Instruction::Ret)?,
+            "clear" => self.emit(Instruction::Cls)?,
+            "exit" => self.emit(Instruction::Exit)?,
+            "lores" => self.emit(Instruction::Lores)?,
+            "hires" => self.emit(Instruction::Hires)?,
+            "scroll-left" => self.emit(Instruction::ScrollLeft)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight)?,
+            "audio" => self.emit(Instruction::Audio)?,
+            "scroll-down" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::ScrollDown { n })?;
+            }
+            "scroll-up" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::ScrollUp { n })?;
+            }
+            "plane" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::Plane { n })?;
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_addr(|nnn| Instruction::Jump { nnn }, &target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_addr(|nnn| Instruction::JumpOffset { nnn }, &target)?;
+            }
+            "bcd" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::Bcd { x })?;
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                let instr = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.next_register()?;
+                    if token == "save" {
+                        Instruction::SaveRange { x, y }
+                    } else {
+                        Instruction::LoadRange { x, y }
+                    }
+                } else if token == "save" {
+                    Instruction::Store { x }
+                } else {
+                    Instruction::Load { x }
+                };
+                self.emit(instr)?;
+            }
+            "saveflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::StoreFlags { x })?;
+            }
+            "loadflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::LoadFlags { x })?;
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next_nibble()?;
+                self.emit(Instruction::Draw { x, y, n })?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let instr = match token.as_str() {
+                    "delay" => Instruction::SetDelay { x },
+                    "buzzer" => Instruction::SetSound { x },
+                    _ => Instruction::Pitch { x },
+                };
+                self.emit(instr)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => self.else_statement()?,
+            "end" => self.end_statement()?,
+            "loop" => {
+                let start = self.pc;
+                self.flow.push(Flow::Loop {
+                    start,
+                    breaks: Vec::new(),
+                    line: self.line,
+                });
+            }
+            "while" => self.while_statement()?,
+            "again" => self.again_statement()?,
+            "then" | "begin" => return self.error(format!("'{}' without 'if'", token)),
+            _ if self.is_register(&token) => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ => {
+                if let Some(value) = parse_number(&token) {
+                    // Bare numbers are data bytes
+                    let byte = self.byte(value)?;
+                    self.emit_byte(byte)?;
+                } else if token.starts_with(':') {
+                    return self.error(format!("unknown directive '{}'", token));
+                } else {
+                    // Anything else is a subroutine call
+                    self.emit_addr(|nnn| Instruction::Call { nnn }, &token)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => {
+                let operand = self.next()?;
+                match operand.as_str() {
+                    "hex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::Font { x })
+                    }
+                    "bighex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::BigFont { x })
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(Instruction::LoadILong)?;
+                        let value = match self.resolve(&target)? {
+                            Some(value) => value,
+                            None => {
+                                self.fixups.push(Fixup {
+                                    addr: self.pc,
+                                    label: target,
+                                    line: self.line,
+                                    long: true,
+                                });
+                                0
+                            }
+                        };
+                        if !(0..=0xffff).contains(&value) {
+                            return self
+                                .error(format!("address {:#x} does not fit in 16 bits", value));
+                        }
+                        self.emit_byte((value >> 8) as u8)?;
+                        self.emit_byte((value & 0xff) as u8)
+                    }
+                    _ => self.emit_addr(|nnn| Instruction::LoadI { nnn }, &operand),
+                }
+            }
+            "+=" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::AddI { x })
+            }
+            _ => self.error(format!("unknown operator 'i {}'", op)),
+        }
+    }
+
+    fn register_statement(&mut self, token: &str) -> Result<()> {
+        let x = self.register(token)?;
+        let op = self.next()?;
+        let operand = self.next()?;
+
+        let instr = match op.as_str() {
+            ":=" => match operand.as_str() {
+                "random" => {
+                    let nn = self.next_byte()?;
+                    Instruction::Random { x, nn }
+                }
+                "delay" => Instruction::GetDelay { x },
+                "key" => Instruction::WaitKey { x },
+                _ if self.is_register(&operand) => Instruction::Move {
+                    x,
+                    y: self.register(&operand)?,
+                },
+                _ => Instruction::LoadImm {
+                    x,
+                    nn: self.byte(self.value(&operand)?)?,
+                },
+            },
+            "+=" if self.is_register(&operand) => Instruction::AddReg {
+                x,
+                y: self.register(&operand)?,
+            },
+            "+=" => Instruction::AddImm {
+                x,
+                nn: self.byte(self.value(&operand)?)?,
+            },
+            "-=" if self.is_register(&operand) => Instruction::Sub {
+                x,
+                y: self.register(&operand)?,
+            },
+            "-=" => {
+                let nn = self.byte(self.value(&operand)?)?;
+                Instruction::AddImm {
+                    x,
+                    nn: nn.wrapping_neg(),
+                }
+            }
+            "=-" => Instruction::SubN {
+                x,
+                y: self.register(&operand)?,
+            },
+            "|=" => Instruction::Or {
+                x,
+                y: self.register(&operand)?,
+            },
+            "&=" => Instruction::And {
+                x,
+                y: self.register(&operand)?,
+            },
+            "^=" => Instruction::Xor {
+                x,
+                y: self.register(&operand)?,
+            },
+            ">>=" => Instruction::ShiftRight {
+                x,
+                y: self.register(&operand)?,
+            },
+            "<<=" => Instruction::ShiftLeft {
+                x,
+                y: self.register(&operand)?,
+            },
+            _ => return self.error(format!("unknown operator '{}'", op)),
+        };
+
+        self.emit(instr)
+    }
+
+    // Control flow
+
+    fn condition(&mut self) -> Result<Cond> {
+        let x = self.next_register()?;
+        let op = self.next()?;
+
+        match op.as_str() {
+            "key" => return Ok(Cond::Key(x)),
+            "-key" => return Ok(Cond::NotKey(x)),
+            _ => {}
+        }
+
+        let rhs = self.next()?;
+        let rhs = if self.is_register(&rhs) {
+            Operand::Reg(self.register(&rhs)?)
+        } else {
+            Operand::Imm(self.byte(self.value(&rhs)?)?)
+        };
+
+        match op.as_str() {
+            "==" => Ok(Cond::Eq(x, rhs)),
+            "!=" => Ok(Cond::Ne(x, rhs)),
+            "<" => Ok(Cond::Lt(x, rhs)),
+            ">" => Ok(Cond::Gt(x, rhs)),
+            "<=" => Ok(Cond::Le(x, rhs)),
+            ">=" => Ok(Cond::Ge(x, rhs)),
+            _ => self.error(format!("unknown comparison '{}'", op)),
+        }
+    }
+
+    /// Sets VF to 1 if `a >= b`, 0 otherwise.
+    fn emit_ge_flag(&mut self, a: Operand, b: Operand) -> Result<()> {
+        match (a, b) {
+            (a, Operand::Reg(b)) => {
+                match a {
+                    Operand::Reg(a) => self.emit(Instruction::Move { x: 0xf, y: a })?,
+                    Operand::Imm(a) => self.emit(Instruction::LoadImm { x: 0xf, nn: a })?,
+                }
+                self.emit(Instruction::Sub { x: 0xf, y: b })
+            }
+            (Operand::Reg(a), Operand::Imm(b)) => {
+                self.emit(Instruction::LoadImm { x: 0xf, nn: b })?;
+                self.emit(Instruction::SubN { x: 0xf, y: a })
+            }
+            (Operand::Imm(_), Operand::Imm(_)) => self.error("cannot compare two constants"),
+        }
+    }
+
+    /// Emits code that skips the following instruction unless `cond` holds.
+    fn emit_condition(&mut self, cond: Cond) -> Result<()> {
+        let instr = match cond {
+            Cond::Eq(x, Operand::Imm(nn)) => Instruction::SkipNeImm { x, nn },
+            Cond::Eq(x, Operand::Reg(y)) => Instruction::SkipNeReg { x, y },
+            Cond::Ne(x, Operand::Imm(nn)) => Instruction::SkipEqImm { x, nn },
+            Cond::Ne(x, Operand::Reg(y)) => Instruction::SkipEqReg { x, y },
+            Cond::Key(x) => Instruction::SkipNotKey { x },
+            Cond::NotKey(x) => Instruction::SkipKey { x },
+            Cond::Lt(x, rhs) | Cond::Ge(x, rhs) => {
+                self.emit_ge_flag(Operand::Reg(x), rhs)?;
+                match cond {
+                    Cond::Lt(..) => Instruction::SkipNeImm { x: 0xf, nn: 0 },
+                    _ => Instruction::SkipEqImm { x: 0xf, nn: 0 },
+                }
+            }
+            Cond::Gt(x, rhs) | Cond::Le(x, rhs) => {
+                self.emit_ge_flag(rhs, Operand::Reg(x))?;
+                match cond {
+                    Cond::Gt(..) => Instruction::SkipNeImm { x: 0xf, nn: 0 },
+                    _ => Instruction::SkipEqImm { x: 0xf, nn: 0 },
+                }
+            }
+        };
+
+        self.emit(instr)
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        let cond = self.condition()?;
+        let line = self.line;
+
+        match self.next()?.as_str() {
+            "then" => self.emit_condition(cond),
+            "begin" => {
+                // Skip the jump over the block when the condition holds
+                self.emit_condition(cond.negate())?;
+                let jump = self.emit_placeholder_jump()?;
+                self.flow.push(Flow::Begin { jump, line });
+                Ok(())
+            }
+            other => self.error(format!("expected 'then' or 'begin', found '{}'", other)),
+        }
+    }
+
+    fn else_statement(&mut self) -> Result<()> {
+        match self.flow.pop() {
+            Some(Flow::Begin { jump, line }) => {
+                let skip_else = self.emit_placeholder_jump()?;
+                let here = self.here()?;
+                self.patch_nnn(jump, here)?;
+                self.flow.push(Flow::Else {
+                    jump: skip_else,
+                    line,
+                });
+                Ok(())
+            }
+            _ => self.error("'else' without 'begin'"),
+        }
+    }
+
+    fn end_statement(&mut self) -> Result<()> {
+        match self.flow.pop() {
+            Some(Flow::Begin { jump, .. }) | Some(Flow::Else { jump, .. }) => {
+                let here = self.here()?;
+                self.patch_nnn(jump, here)
+            }
+            _ => self.error("'end' without 'begin'"),
+        }
+    }
+
+    fn while_statement(&mut self) -> Result<()> {
+        let cond = self.condition()?;
+        if !self
+            .flow
+            .iter()
+            .any(|flow| matches!(flow, Flow::Loop { .. }))
+        {
+            return self.error("'while' outside of 'loop'");
+        }
+
+        self.emit_condition(cond.negate())?;
+        let jump = self.emit_placeholder_jump()?;
+
+        for flow in self.flow.iter_mut().rev() {
+            if let Flow::Loop { breaks, .. } = flow {
+                breaks.push(jump);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn again_statement(&mut self) -> Result<()> {
+        match self.flow.pop() {
+            Some(Flow::Loop { start, breaks, .. }) => {
+                let start = self.address(start as i64)?;
+                self.emit(Instruction::Jump { nnn: start })?;
+
+                let here = self.here()?;
+                for jump in breaks {
+                    self.patch_nnn(jump, here)?;
+                }
+                Ok(())
+            }
+            _ => self.error("'again' without 'loop'"),
+        }
+    }
+
+    // Macros
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.new_name()?;
+        let mut args = Vec::new();
+
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+
+        let body = self.block()?;
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    /// Tokens up to the matching `}`; the opening brace is already consumed.
+    fn block(&mut self) -> Result<Vec<Token>> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return self.error("missing '}'"),
+            };
+            self.line = token.line;
+
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.error(format!("too many macro expansions in '{}'", name));
+        }
+
+        let arg_count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for i in 0..arg_count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[i].clone(), value);
+        }
+
+        let line = self.line;
+        let expanded: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: values.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+            })
+            .collect();
+
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // :calc expressions, evaluated right to left like Octo
+
+    fn calc_block(&mut self) -> Result<i64> {
+        let tokens = self.block()?;
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+
+        if pos != tokens.len() {
+            return self.error(format!("unexpected '{}' in expression", tokens[pos].text));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[Token], pos: &mut usize) -> Result<i64> {
+        let lhs = self.term(tokens, pos)?;
+
+        let op = match tokens.get(*pos) {
+            Some(token) if token.text != ")" => token.text.clone(),
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.expression(tokens, pos)?;
+
+        let value = match op.as_str() {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return self.error("division by zero"),
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "<" => i64::from(lhs < rhs),
+            ">" => i64::from(lhs > rhs),
+            "<=" => i64::from(lhs <= rhs),
+            ">=" => i64::from(lhs >= rhs),
+            "==" => i64::from(lhs == rhs),
+            "!=" => i64::from(lhs != rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            _ => return self.error(format!("unknown operator '{}'", op)),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> Result<i64> {
+        let token = match tokens.get(*pos) {
+            Some(token) => token.text.as_str(),
+            None => return self.error("incomplete expression"),
+        };
+        *pos += 1;
+
+        match token {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(token) if token.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => self.error("missing ')'"),
+                }
+            }
+            "-" => Ok(-self.term(tokens, pos)?),
+            "~" => Ok(!self.term(tokens, pos)?),
+            "!" => Ok(i64::from(self.term(tokens, pos)? == 0)),
+            "HERE" => Ok(self.pc as i64),
+            _ => self.value(token),
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(token: &str) -> Option<u8> {
+    let index = token.strip_prefix('v').or(token.strip_prefix('V'))?;
+    if index.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(index, 16).ok()
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+    use std::fs;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1]))
+            .collect()
+    }
+
+    #[test]
+    fn assembles_instructions() {
+        let program = assemble(
+            "
+            : main
+                clear
+                v1 := 0x11   # comment
+                v1 += 2
+                v2 -= 1
+                i := ball
+                sprite v1 v2 5
+                jump main
+            : ball
+                0xff 0x81
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words(&program.rom),
+            vec![0x00e0, 0x6111, 0x7102, 0x72ff, 0xa20e, 0xd125, 0x1200, 0xff81]
+        );
+        assert_eq!(program.labels["main"], 0x200);
+        assert_eq!(program.labels["ball"], 0x20e);
+    }
+
+    #[test]
+    fn directives() {
+        let program = assemble(
+            "
+            :const SPEED 3
+            :alias px v4
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro bump reg amount { reg += amount }
+            px := SPEED
+            bump px DOUBLE
+            :byte { 2 * 3 }
+            :org 0x208
+            :byte 7
+            ",
+        )
+        .unwrap();
+
+        // right to left: 3 * (2 + 1)
+        assert_eq!(
+            program.rom,
+            vec![0x64, 0x03, 0x74, 0x09, 0x06, 0x00, 0x00, 0x00, 0x07]
+        );
+    }
+
+    #[test]
+    fn structured_control_flow() {
+        let program = assemble(
+            "
+            loop
+                while v0 != 5
+                v0 += 1
+                if v1 == 2 then v2 := 1
+                if v3 key begin
+                    v4 := 1
+                else
+                    v4 := 2
+                end
+            again
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words(&program.rom),
+            vec![
+                0x4005, // while: skip the exit jump while v0 != 5
+                0x1216, // exit loop
+                0x7001, 0x4102, 0x6201, //
+                0xe39e, // if v3 key begin: skip jump if pressed
+                0x1212, 0x6401, 0x1214, // else
+                0x6402, //
+                0x1200, // again
+            ]
+        );
+    }
+
+    #[test]
+    fn comparison_conditions() {
+        let program = assemble("if v1 < v2 then clear\nif v3 >= 10 then clear").unwrap();
+
+        assert_eq!(
+            words(&program.rom),
+            vec![0x8f10, 0x8f25, 0x4f00, 0x00e0, 0x6f0a, 0x8f37, 0x3f00, 0x00e0]
+        );
+    }
+
+    #[test]
+    fn forward_calls_and_long_loads() {
+        let program = assemble("draw\ni := long data\n: draw ;\n: data 1").unwrap();
+
+        assert_eq!(
+            program.rom,
+            vec![0x22, 0x06, 0xf0, 0x00, 0x02, 0x08, 0x00, 0xee, 0x01]
+        );
+    }
+
+    #[test]
+    fn errors_carry_line_numbers() {
+        let err = assemble("clear\n\nv1 := 300").err().unwrap();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.to_string(), "line 3: value 300 does not fit in a byte");
+
+        let err = assemble("clear\njump nowhere").err().unwrap();
+        assert_eq!(err.to_string(), "line 2: undefined label 'nowhere'");
+
+        let err = assemble("loop\nclear").err().unwrap();
+        assert_eq!(err.to_string(), "line 1: unterminated 'loop'");
+
+        assert_eq!(assemble("v1 ~= v2").err().unwrap().line, 1);
+
+        let err = assemble("clear\n:org 0x100\nclear").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 2: address 0x100 is before the ROM at 0x200"
+        );
+    }
+
+    #[test]
+    fn symbol_map_lists_labels() {
+        let program = assemble(": main\nclear\n: loop-top\njump loop-top").unwrap();
+
+        assert_eq!(program.symbol_map(), "0x0200 main\n0x0202 loop-top\n");
+        assert_eq!(program.symbols().get(&0x202).unwrap(), "loop-top");
+    }
+
+    #[test]
+    fn reassembles_disassembled_roms() {
+        for name in [
+            "roms/2-ibm-logo.ch8",
+            "roms/3-corax+.ch8",
+            "roms/br8kout.ch8",
+        ] {
+            let rom = fs::read(name).unwrap();
+            let source = disasm::disassemble(&rom, Syntax::Octo);
+
+            assert_eq!(assemble(&source).unwrap().rom, rom, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+use crate::disasm;
+use crate::instruction::Instruction;
+use crate::symbols::SymbolMap;
+
+const ENTRY_POINT: u16 = 0x200;
+
+/// Attributes executed instructions to the chain of subroutines that were
+/// active, following 2NNN calls and 00EE returns.
+pub struct CallGraph {
+    /// Entry addresses of the active subroutines, the program entry first
+    stack: Vec<u16>,
+    /// Instructions executed per call chain
+    samples: HashMap<Vec<u16>, u64>,
+    calls: HashMap<u16, u64>,
+}
+
+/// Cycle counts for one subroutine
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub calls: u64,
+    /// Instructions executed in the subroutine and everything it called
+    pub inclusive: u64,
+    /// Instructions executed in the subroutine itself
+    pub exclusive: u64,
+}
+
+impl Default for CallGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        Self {
+            stack: vec![ENTRY_POINT],
+            samples: HashMap::new(),
+            calls: HashMap::new(),
+        }
+    }
+
+    /// Counts an instruction against the current call chain. Called before it executes.
+    pub fn record(&mut self) {
+        match self.samples.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.samples.insert(self.stack.clone(), 1);
+            }
+        }
+    }
+
+    /// Follows calls and returns once `opcode` has executed. `depth` is the
+    /// length of the interpreter stack afterwards, which keeps the chain in
+    /// sync when a ROM manipulates the stack in other ways.
+    pub fn executed(&mut self, opcode: u16, depth: usize) {
+        match Instruction::decode(opcode) {
+            Instruction::Call { nnn } => {
+                self.stack.push(nnn);
+                *self.calls.entry(nnn).or_insert(0) += 1;
+            }
+            Instruction::Ret if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+
+        self.stack.truncate(depth + 1);
+    }
+
+    /// Inclusive and exclusive counts per subroutine entry address
+    pub fn totals(&self) -> BTreeMap<u16, Totals> {
+        let mut totals: BTreeMap<u16, Totals> = BTreeMap::new();
+
+        for (chain, count) in &self.samples {
+            let mut seen = Vec::new();
+            for addr in chain {
+                // Recursion must not count the same cycles twice
+                if !seen.contains(addr) {
+                    totals.entry(*addr).or_default().inclusive += count;
+                    seen.push(*addr);
+                }
+            }
+
+            if let Some(last) = chain.last() {
+                totals.entry(*last).or_default().exclusive += count;
+            }
+        }
+
+        for (addr, calls) in &self.calls {
+            totals.entry(*addr).or_default().calls = *calls;
+        }
+
+        totals
+    }
+
+    /// One `main;sub_2A4;draw 1234` line per call chain, as read by
+    /// flamegraph.pl, inferno and speedscope.
+    pub fn folded(&self, names: &Names) -> String {
+        let mut lines: Vec<String> = self
+            .samples
+            .iter()
+            .map(|(chain, count)| {
+                let chain: Vec<String> = chain.iter().map(|addr| names.get(*addr)).collect();
+                format!("{} {}", chain.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    pub fn write_folded(&self, path: &str, names: &Names) -> io::Result<()> {
+        fs::write(path, self.folded(names))
+    }
+
+    /// Subroutines sorted by inclusive count
+    pub fn report(&self, names: &Names) -> String {
+        let mut totals: Vec<(u16, Totals)> = self.totals().into_iter().collect();
+        totals.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+
+        let mut out = format!(
+            "{:<24} {:>8} {:>12} {:>12}\n",
+            "subroutine", "calls", "inclusive", "exclusive"
+        );
+        for (addr, totals) in totals {
+            out.push_str(&format!(
+                "{:<24} {:>8} {:>12} {:>12}\n",
+                names.get(addr),
+                totals.calls,
+                totals.inclusive,
+                totals.exclusive
+            ));
+        }
+
+        out
+    }
+}
+
+/// Subroutine names, from a symbol map where available and from the
+/// disassembler's generated labels otherwise.
+pub struct Names {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Names {
+    pub fn new(rom: &[u8], symbols: &SymbolMap) -> Self {
+        let lines = disasm::decode_rom(rom);
+        let mut labels = disasm::auto_labels(&lines);
+        labels.extend(symbols.iter().map(|(addr, name)| (*addr, name.clone())));
+
+        Self { labels }
+    }
+
+    pub fn get(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(name) => name.clone(),
+            None if addr == ENTRY_POINT => "main".to_string(),
+            None => format!("sub_{:03X}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// main calls A twice, A calls B once
+    fn sample() -> CallGraph {
+        let mut graph = CallGraph::new();
+        let steps: [(u16, usize); 9] = [
+            (0x6000, 0),
+            (0x2300, 1), // main: call A
+            (0x2400, 2), // A: call B
+            (0x6000, 2), // B
+            (0x00ee, 1), // B: return
+            (0x00ee, 0), // A: return
+            (0x2300, 1), // main: call A
+            (0x00ee, 0), // A: return
+            (0x1200, 0), // main
+        ];
+
+        for (opcode, depth) in steps {
+            graph.record();
+            graph.executed(opcode, depth);
+        }
+
+        graph
+    }
+
+    #[test]
+    fn inclusive_and_exclusive_counts() {
+        let totals = sample().totals();
+
+        assert_eq!(
+            totals[&0x200],
+            Totals {
+                calls: 0,
+                inclusive: 9,
+                exclusive: 4
+            }
+        );
+        assert_eq!(
+            totals[&0x300],
+            Totals {
+                calls: 2,
+                inclusive: 5,
+                exclusive: 3
+            }
+        );
+        assert_eq!(
+            totals[&0x400],
+            Totals {
+                calls: 1,
+                inclusive: 2,
+                exclusive: 2
+            }
+        );
+    }
+
+    #[test]
+    fn folded_stacks_use_symbols_then_generated_labels() {
+        // 0x200: call 0x204, 0x202: jump 0x202, 0x204: call 0x208,
+        // 0x206: return, 0x208: return
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xee, 0x00, 0xee];
+        let mut symbols = SymbolMap::new();
+        symbols.insert(0x208, "draw".to_string());
+        let names = Names::new(&rom, &symbols);
+
+        let mut graph = CallGraph::new();
+        for (opcode, depth) in [(0x2204, 1), (0x2208, 2), (0x00ee, 1), (0x00ee, 0)] {
+            graph.record();
+            graph.executed(opcode, depth);
+        }
+
+        assert_eq!(
+            graph.folded(&names),
+            "main 1\nmain;sub_204 2\nmain;sub_204;draw 1\n"
+        );
+    }
+
+    #[test]
+    fn resyncs_with_interpreter_stack() {
+        let mut graph = CallGraph::new();
+
+        graph.executed(0x2300, 1);
+        graph.executed(0x2400, 2);
+        // The ROM dropped both frames without returning
+        graph.executed(0x1200, 0);
+        graph.record();
+
+        assert_eq!(graph.samples[&vec![0x200]], 1);
+    }
+}
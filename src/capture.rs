@@ -0,0 +1,304 @@
+//! Video capture of every emulated frame: an animated GIF with the sound
+//! timer's beep as a WAV next to it, and raw RGB frames for piping into an
+//! external encoder, e.g.
+//!
+//! ```text
+//! cpu-emulator run game.ch8 --raw-frames | ffmpeg -f rawvideo -pix_fmt rgb24 -s 64x32 -r 60 -i - game.mp4
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::palette::Palette;
+use crate::screen::Screen;
+use crate::screenshot;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+/// Frequency of the beep while the sound timer runs
+pub const TONE: u32 = 440;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+/// Pixel scale of GIF captures
+pub const GIF_SCALE: u16 = 4;
+
+/// Where each frame goes. All sinks are optional.
+#[derive(Default)]
+pub struct Capture {
+    palette: Palette,
+    gif: Option<Gif<BufWriter<File>>>,
+    wav: Option<Wav<BufWriter<File>>>,
+    raw: Option<Box<dyn Write>>,
+}
+
+impl Capture {
+    pub fn new(palette: Palette) -> Self {
+        Self {
+            palette,
+            ..Default::default()
+        }
+    }
+
+    /// Records an animated GIF to `path` and the audio to the same path with
+    /// a `.wav` extension.
+    pub fn gif(mut self, path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        self.gif = Some(Gif::new(file, &self.palette, GIF_SCALE)?);
+
+        let file = BufWriter::new(File::create(path.with_extension("wav"))?);
+        self.wav = Some(Wav::new(file)?);
+
+        Ok(self)
+    }
+
+    /// Writes every frame as native resolution RGB24 to `out`
+    pub fn raw(mut self, out: Box<dyn Write>) -> Self {
+        self.raw = Some(out);
+        self
+    }
+
+    /// Adds one emulated frame
+    pub fn frame(&mut self, screen: &Screen, beeping: bool) -> io::Result<()> {
+        if let Some(gif) = self.gif.as_mut() {
+            gif.frame(screen)?;
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            wav.frame(beeping)?;
+        }
+        if let Some(raw) = self.raw.as_mut() {
+            raw.write_all(&screenshot::to_rgb(screen, &self.palette, 1))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last GIF frame and the WAV sizes
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(gif) = self.gif {
+            gif.finish()?;
+        }
+        if let Some(wav) = self.wav {
+            wav.finish()?;
+        }
+        if let Some(mut raw) = self.raw {
+            raw.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The capture asked for on the command line, `None` if neither a GIF nor raw
+/// frames were
+pub fn start(
+    gif: Option<&Path>,
+    raw_frames: bool,
+    palette: Palette,
+) -> io::Result<Option<Capture>> {
+    if gif.is_none() && !raw_frames {
+        return Ok(None);
+    }
+
+    let mut capture = Capture::new(palette);
+    if let Some(path) = gif {
+        capture = capture.gif(path)?;
+    }
+    if raw_frames {
+        capture = capture.raw(Box::new(BufWriter::new(io::stdout())));
+    }
+
+    Ok(Some(capture))
+}
+
+/// `<dir>/chip8-<time>.gif`, for captures started from the hotkey
+pub fn gif_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{}.gif",
+        screenshot::timestamp(std::time::SystemTime::now())
+    ))
+}
+
+/// GIF delays count in 1/100 s and viewers slow down anything shorter than
+/// 2, so a frame that changes sooner than that replaces the one before it.
+/// Frames that do not change only lengthen the previous one.
+pub struct Gif<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: u16,
+    /// Frame waiting for its delay and the frame number it started at
+    pending: Option<(Vec<u8>, u64)>,
+    frames: u64,
+}
+
+impl<W: Write> Gif<W> {
+    pub fn new(out: W, palette: &Palette, scale: u16) -> io::Result<Self> {
+        let colors: Vec<u8> = [palette.off(), palette.on()].concat();
+        let width = 64 * scale;
+        let height = 32 * scale;
+        let mut encoder =
+            gif::Encoder::new(out, width, height, &colors).map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            encoder,
+            scale,
+            pending: None,
+            frames: 0,
+        })
+    }
+
+    pub fn frame(&mut self, screen: &Screen) -> io::Result<()> {
+        let scale = usize::from(self.scale);
+        let pixels: Vec<u8> = (0..screen.height * scale)
+            .flat_map(|y| (0..screen.width * scale).map(move |x| (x, y)))
+            .map(|(x, y)| screen.get(x / scale, y / scale))
+            .collect();
+
+        let now = self.frames;
+        self.frames += 1;
+
+        match self.pending.take() {
+            None => self.pending = Some((pixels, now)),
+            Some((pending, start)) if pending == pixels => self.pending = Some((pending, start)),
+            Some((pending, start)) if centis(now) - centis(start) >= 2 => {
+                self.write(&pending, start, now)?;
+                self.pending = Some((pixels, now));
+            }
+            Some((_, start)) => self.pending = Some((pixels, start)),
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some((pending, start)) = self.pending.take() {
+            self.write(&pending, start, self.frames)?;
+        }
+
+        self.encoder.into_inner()
+    }
+
+    fn write(&mut self, pixels: &[u8], start: u64, end: u64) -> io::Result<()> {
+        let (width, height) = (64 * self.scale, 32 * self.scale);
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+        frame.delay = (centis(end) - centis(start)).max(2) as u16;
+
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
+
+/// Time in 1/100 s at which frame `n` starts
+fn centis(frame: u64) -> u64 {
+    frame * 100 / 60
+}
+
+/// Half periods of the tone before sample `n`
+fn half_periods(n: u32) -> u64 {
+    u64::from(n) * u64::from(TONE) * 2 / u64::from(SAMPLE_RATE)
+}
+
+/// 16 bit mono PCM with a square wave while beeping
+pub struct Wav<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> Wav<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        // Sizes are filled in by `finish`
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // Mono
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Bytes per second
+        out.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+        out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        out.write_all(b"data\0\0\0\0")?;
+
+        Ok(Self { out, samples: 0 })
+    }
+
+    pub fn frame(&mut self, beeping: bool) -> io::Result<()> {
+        let mut data = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = match beeping {
+                // The sample count keeps the wave continuous across frames
+                true if half_periods(self.samples).is_multiple_of(2) => AMPLITUDE,
+                true => -AMPLITUDE,
+                false => 0,
+            };
+            data.extend(sample.to_le_bytes());
+            self.samples += 1;
+        }
+
+        self.out.write_all(&data)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn wav_header_and_samples() {
+        let mut wav = Wav::new(Cursor::new(Vec::new())).unwrap();
+        wav.frame(false).unwrap();
+        wav.frame(true).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        let data_len = SAMPLES_PER_FRAME as usize * 2 * 2;
+        assert_eq!(bytes.len(), 44 + data_len);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(bytes[4..8], (36 + data_len as u32).to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], (data_len as u32).to_le_bytes());
+
+        let sample = |n: usize| i16::from_le_bytes([bytes[44 + n * 2], bytes[45 + n * 2]]);
+        assert_eq!(sample(0), 0);
+        let beep: Vec<i16> = (735..1470).map(sample).collect();
+        assert!(beep.contains(&AMPLITUDE) && beep.contains(&-AMPLITUDE));
+    }
+
+    #[test]
+    fn gif_merges_short_and_unchanged_frames() {
+        let mut lit = Screen::new();
+        lit.draw_sprite(0, 0, &[0x80], true);
+
+        let mut gif = Gif::new(Vec::new(), &Palette::default(), 1).unwrap();
+        // The blank first frame is gone within 2/100 s, so the lit frame
+        // replaces it
+        let blank = Screen::new();
+        for screen in [&blank, &lit, &lit, &blank, &blank, &blank] {
+            gif.frame(screen).unwrap();
+        }
+        let bytes = gif.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decoder.read_info(bytes.as_slice()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.buffer[0], frame.delay));
+        }
+
+        // Frames 0-2 end at 5/100 s, frames 3-5 at 10/100 s
+        assert_eq!(frames, vec![(1, 5), (0, 5)]);
+    }
+}
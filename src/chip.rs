@@ -1,37 +1,70 @@
 #![allow(dead_code)]
 #![allow(non_snake_case)]
 
-extern crate sdl2;
-
-use rand::Rng;
-use sdl2::rect::Rect;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 //use std::fmt::Display;
-use std::fs;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
-use crate::display::Display;
-use crate::input::{Input, InputAction};
-
-const NNN: u16 = 0x0fff;
-const NN: u16 = 0x00ff;
-const N: u16 = 0x000f;
-const X: u16 = 0x0f00;
-const Y: u16 = 0x00f0;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::callgraph::CallGraph;
+use crate::coverage::{Access, Coverage};
+use crate::filter::Filter;
+use crate::instruction::Instruction;
+use crate::movie::{self, Movie};
+use crate::palette::Palette;
+use crate::phosphor::Persistence;
+use crate::profile::Profiler;
+use crate::quirks::Quirks;
+use crate::screen::{Rotation, Screen};
+use crate::trace::{TraceStep, Tracer};
+#[cfg(feature = "sdl")]
+use crate::window::Externals;
 
 const ADDR_OFFSET: usize = 0x200;
-
-pub struct Externals {
-    pub display: Display,
-    pub input: Input,
+const MEMORY_SIZE: usize = 0x1000;
+const FONT_ADDR: usize = 0x050;
+/// Return addresses the stack holds, like the 16 levels of SUPER-CHIP
+pub const STACK_SIZE: usize = 16;
+
+/// Hex digit sprites 0-F, 5 bytes each
+const FONT: [u8; 80] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+    0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+/// A ROM bug the interpreter papers over. The machine carries on regardless,
+/// `Chip8::fault` keeps the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    UnknownInstruction { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
 }
 
-impl Externals {
-    fn new() -> Self {
-        let mut display = Display::new();
-
-        Self {
-            input: Input::new(&mut display),
-            display: display,
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::UnknownInstruction { pc, opcode } => {
+                write!(f, "unknown instruction {:#06x} at {:#05x}", opcode, pc)
+            }
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "return without call at {:#05x}", pc),
         }
     }
 }
@@ -40,65 +73,227 @@ pub struct Chip8 {
     pub stack: Vec<u16>,
     pub delay_timer: u16,
     pub sound_timer: u16,
+    #[cfg(feature = "sdl")]
     pub externals: Option<Externals>,
-    registers: [u8; 16],
-    ip: usize, // Instruction pointer
-    ireg: u16,
-    rom_bytes: Vec<u8>,
+    pub tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub callgraph: Option<CallGraph>,
+    pub coverage: Option<Coverage>,
+    /// Input movie being recorded
+    pub recording: Option<Movie>,
+    pub frame: u64,
+    /// First fault since the machine started
+    pub fault: Option<Fault>,
+    pub screen: Screen,
+    pub quirks: Quirks,
+    /// Key pad state, set by the frontend once per frame
+    pub key_pad: [bool; 16],
+    /// Key pad state of the previous frame, to spot new presses
+    prev_key_pad: [bool; 16],
+    /// Key pressed while FX0A waits for its release
+    key_wait: Option<u8>,
+    /// Set by DXYN when the display wait quirk ends the frame early
+    pub(crate) vblank_wait: bool,
+    /// CXNN draws from `rng`, which starts from `seed`
+    seed: u64,
+    rng: StdRng,
+    pub(crate) registers: [u8; 16],
+    pub(crate) ip: usize, // Instruction pointer
+    pub(crate) ireg: u16,
+    pub(crate) rom_bytes: Vec<u8>,
+    /// The ROM as loaded, before the program changed memory
+    rom: Vec<u8>,
 }
 
 impl Default for Chip8 {
     fn default() -> Self {
+        let seed = rand::random();
+
         Self {
             stack: Vec::new(),
             delay_timer: 0,
             sound_timer: 0,
             registers: [0; 16],
+            #[cfg(feature = "sdl")]
             externals: None,
+            tracer: None,
+            profiler: None,
+            callgraph: None,
+            coverage: None,
+            recording: None,
+            frame: 0,
+            fault: None,
+            screen: Screen::new(),
+            quirks: Quirks::default(),
+            key_pad: [false; 16],
+            prev_key_pad: [false; 16],
+            key_wait: None,
+            vblank_wait: false,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
             ip: ADDR_OFFSET,
             ireg: 0,
-            rom_bytes: read_rom("roms/2-ibm-logo.ch8"),
+            rom_bytes: load_rom(&[]),
+            rom: Vec::new(),
         }
     }
 }
 
 impl Chip8 {
-    fn new(rom_name: &str) -> Self {
+    /// Starts `rom` from scratch. The window, the quirks and the seed stay.
+    /// The profiler, call graph and coverage start over, so their reports
+    /// describe `rom` alone. A trace can't be taken back, so it ends here.
+    pub fn reset(&mut self, rom: &[u8]) {
+        let seed = self.seed;
+        *self = Chip8 {
+            #[cfg(feature = "sdl")]
+            externals: self.externals.take(),
+            profiler: self.profiler.as_ref().map(|_| Profiler::new()),
+            callgraph: self.callgraph.as_ref().map(|_| CallGraph::new()),
+            coverage: self.coverage.as_ref().map(|_| Coverage::new()),
+            quirks: self.quirks,
+            rom_bytes: load_rom(rom),
+            rom: rom.to_vec(),
+            ..Default::default()
+        };
+        self.set_seed(seed);
+    }
+
+    /// A headless machine with `rom` loaded at 0x200
+    pub fn from_rom(rom: &[u8], quirks: Quirks) -> Self {
         Chip8 {
-            rom_bytes: read_rom(rom_name),
-            externals: Some(Externals::new()),
+            rom_bytes: load_rom(rom),
+            rom: rom.to_vec(),
+            quirks,
             ..Default::default()
         }
     }
 
-    fn display_mut(&mut self) -> Option<&mut Display> {
-        if self.externals.is_none() {
-            return None;
+    pub fn new_by_bytes(mut rom_bytes: Vec<u8>) -> Self {
+        rom_bytes.resize(rom_bytes.len().max(MEMORY_SIZE), 0);
+
+        Chip8 {
+            rom_bytes,
+            ..Default::default()
         }
+    }
 
-        Some(&mut self.externals.as_mut().unwrap().display)
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
     }
 
-    fn input_mut(&mut self) -> Option<&mut Input> {
-        if self.externals.is_none() {
-            return None;
-        }
-        Some(&mut self.externals.as_mut().unwrap().input)
+    pub fn pc(&self) -> u16 {
+        u16_from_usize(self.ip)
     }
 
-    pub fn new_by_bytes(rom_bytes: Vec<u8>) -> Self {
-        Chip8 {
-            rom_bytes: rom_bytes,
-            ..Default::default()
-        }
+    pub fn ireg(&self) -> u16 {
+        self.ireg
     }
 
+    pub fn memory(&self) -> &[u8] {
+        &self.rom_bytes
+    }
+
+    /// The ROM as it was loaded at 0x200
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts the random number generator, so CXNN repeats the same values
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Hash over registers, timers, stack, memory and screen, to check that
+    /// two runs ended up in the same state
+    pub fn state_hash(&self) -> u64 {
+        let mut state = Vec::with_capacity(self.rom_bytes.len() + self.screen.pixels().len() + 64);
+        state.extend(self.registers);
+        state.extend(u16_from_usize(self.ip).to_be_bytes());
+        state.extend(self.ireg.to_be_bytes());
+        state.extend(self.delay_timer.to_be_bytes());
+        state.extend(self.sound_timer.to_be_bytes());
+        state.extend(self.stack.iter().flat_map(|addr| addr.to_be_bytes()));
+        state.extend(&self.rom_bytes);
+        state.extend(self.screen.pixels());
+
+        movie::hash(&state)
+    }
+
+    /// Whether the next instruction jumps to itself, the usual way for a
+    /// ROM to stop
+    pub fn halted(&self) -> bool {
+        let [high, low] = self.pc().to_be_bytes();
+        self.ip < 0x1000 && self.mem(self.ip) == 0x10 | high && self.mem(self.ip + 1) == low
+    }
+
+    fn raise(&mut self, fault: Fault) {
+        self.fault.get_or_insert(fault);
+    }
+
+    /// Address of the instruction being executed
+    fn current_pc(&self) -> u16 {
+        u16_from_usize(self.ip - 2)
+    }
+
+    /// A full stack drops its oldest return address
     fn push_stack(&mut self, val: u16) {
+        if self.stack.len() == STACK_SIZE {
+            self.raise(Fault::StackOverflow {
+                pc: self.current_pc(),
+            });
+            self.stack.remove(0);
+        }
         self.stack.push(val);
     }
 
-    fn pop_stack(&mut self) -> u16 {
-        self.stack.pop().unwrap()
+    fn pop_stack(&mut self) -> Option<u16> {
+        self.stack.pop()
+    }
+
+    /// Runs up to `instructions` instructions, then ends the frame. With the
+    /// display wait quirk a draw ends the frame early. Returns how many ran.
+    pub fn run_frame(&mut self, instructions: u32) -> u32 {
+        self.run_frame_until(instructions, |_| false).0
+    }
+
+    /// Like `run_frame`, but stops before the next instruction once `stop`
+    /// holds and leaves the frame open, to be continued with the
+    /// instructions that are left. Returns how many ran and whether the frame
+    /// ended.
+    pub fn run_frame_until(
+        &mut self,
+        instructions: u32,
+        mut stop: impl FnMut(&Chip8) -> bool,
+    ) -> (u32, bool) {
+        let mut executed = 0;
+        while executed < instructions && !self.vblank_wait {
+            if stop(self) {
+                return (executed, false);
+            }
+            self.step();
+            executed += 1;
+        }
+
+        self.end_frame();
+        (executed, true)
+    }
+
+    /// Called once per 60 Hz frame
+    pub(crate) fn end_frame(&mut self) {
+        self.decrement_timers();
+        self.frame += 1;
+        self.vblank_wait = false;
+        self.prev_key_pad = self.key_pad;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
     }
 
     fn decrement_timers(&mut self) {
@@ -111,44 +306,6 @@ impl Chip8 {
         }
     }
 
-    fn draw_instr(&mut self, xu16: u16, yu16: u16, height: u16) {
-        self.externals.as_mut().unwrap().display.to_on_color();
-        if self.externals.is_some() {
-            let x: i32 = i32::try_from(xu16).unwrap();
-            let y: i32 = i32::try_from(yu16).unwrap();
-
-            for isprite in 0..height {
-                let hpp_i32 = i32::try_from(Display::get_height_per_pixel()).unwrap();
-                let wpp_i32 = i32::try_from(Display::get_width_per_pixel()).unwrap();
-
-                let sprite = self.rom_bytes[usize::try_from(self.ireg + isprite).unwrap()];
-
-                let isprite_i32 = i32::try_from(isprite).unwrap();
-
-                for bit in 0..8 {
-                    if (1 << (7 - bit)) & sprite != 0 {
-                        self.externals
-                            .as_mut()
-                            .unwrap()
-                            .display
-                            .canvas
-                            .fill_rect(Rect::new(
-                                x * wpp_i32 + bit * wpp_i32,
-                                y * hpp_i32 + isprite_i32 * hpp_i32,
-                                Display::get_width_per_pixel(),
-                                Display::get_height_per_pixel(),
-                            ))
-                            .unwrap();
-                    }
-                }
-            }
-        }
-    }
-
-    fn draw(&mut self) {
-        self.display_mut().unwrap().canvas.present();
-    }
-
     fn get_delay_timer(&mut self) -> u16 {
         self.delay_timer
     }
@@ -160,347 +317,370 @@ impl Chip8 {
         bytes
     }
 
-    fn skip_instructions(&mut self, num_instr: u16) {
-        self.ip += usize_from_u16(num_instr * 2);
+    /// Fetches and executes a single instruction
+    pub fn step(&mut self) {
+        let pc = u16_from_usize(self.ip);
+        let instr = self.fetch();
+
+        if self.tracer.is_some() {
+            self.trace(pc, instr);
+        }
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, instr);
+        }
+
+        if let Some(callgraph) = self.callgraph.as_mut() {
+            callgraph.record();
+        }
+
+        self.cover(Access::Executed, pc, 2);
+
+        self.decode(instr);
+        self.ip %= self.rom_bytes.len();
+
+        if let Some(callgraph) = self.callgraph.as_mut() {
+            callgraph.executed(instr, self.stack.len());
+        }
     }
 
-    fn decode(&mut self, instr: u16) {
-        print_u16_hex(instr);
+    fn trace(&mut self, pc: u16, instr: u16) {
+        let Some(tracer) = self.tracer.as_mut() else {
+            return;
+        };
 
-        let ixreg = get_X(instr);
-        let iyreg = get_Y(instr);
+        if !tracer.wants(self.frame, pc, instr) {
+            return;
+        }
 
-        let xreg = self.get_X_register_value(instr);
-        let yreg = self.get_Y_register_value(instr);
+        let long = match Instruction::decode(instr) {
+            Instruction::LoadILong => self
+                .rom_bytes
+                .get(self.ip..self.ip + 2)
+                .map(|word| u16::from(word[0]) << 8 | u16::from(word[1])),
+            _ => None,
+        };
 
-        match (instr & 0xf000) >> 12 {
-            0 => {
-                // clear screen
-                match instr & NNN {
-                    0xe0 => {
-                        // Clear screen
-                        self.display_mut().unwrap().clear();
-                    }
-                    0xee => {
-                        // Return to address from address in stack
-                        let ret_addr = self.stack.pop().unwrap();
-                        self.ip = usize::try_from(ret_addr).unwrap();
-                    }
-                    _ => print_unknown_instr(instr),
+        tracer.record(&TraceStep {
+            frame: self.frame,
+            pc,
+            opcode: instr,
+            long,
+            registers: &self.registers,
+            ireg: self.ireg,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        });
+    }
+
+    /// Marks the bytes `mem` would access, wrapping at the end of memory
+    fn cover(&mut self, access: Access, addr: u16, len: usize) {
+        let size = self.rom_bytes.len();
+        if let Some(coverage) = self.coverage.as_mut() {
+            for addr in usize_from_u16(addr)..usize_from_u16(addr) + len {
+                coverage.mark(access, addr % size, 1);
+            }
+        }
+    }
+
+    fn skip_instructions(&mut self, num_instr: u16) {
+        self.ip += usize_from_u16(num_instr * 2);
+    }
+
+    fn decode(&mut self, instr: u16) {
+        match Instruction::decode(instr) {
+            Instruction::Sys { .. } => {
+                // Machine code routines only ran on the original hardware,
+                // interpreters skip them
+            }
+            Instruction::Cls => {
+                // Clear screen
+                self.screen.clear();
+            }
+            Instruction::Ret => {
+                // Return to address from address in stack, a return without
+                // a call is ignored, apart from the fault
+                match self.pop_stack() {
+                    Some(ret_addr) => self.ip = usize_from_u16(ret_addr),
+                    None => self.raise(Fault::StackUnderflow {
+                        pc: self.current_pc(),
+                    }),
                 }
             }
-            1 => {
-                // jump
-                let address: u16 = instr & NNN;
-                self.ip = usize_from_u16(address);
+            Instruction::Jump { nnn } => {
+                self.ip = usize_from_u16(nnn);
             }
-            2 => {
-                // jump to address and store current address in stack
-                let address = instr & NNN;
-                self.push_stack(u16_from_usize(self.ip));
-                self.ip = usize_from_u16(address);
+            Instruction::Call { nnn } => {
+                // jump to address and store current address in stack, which
+                // wraps like PC when the call is the last instruction
+                self.push_stack(u16_from_usize(self.ip % self.rom_bytes.len()));
+                self.ip = usize_from_u16(nnn);
             }
-            3 => {
-                if self.register_equal(ixreg, instr & NN) {
+            Instruction::SkipEqImm { x, nn } => {
+                if self.reg(x) == nn {
                     self.skip_instructions(1);
                 }
             }
-            4 => {
-                if xreg != instr & NN {
+            Instruction::SkipNeImm { x, nn } => {
+                if self.reg(x) != nn {
                     self.skip_instructions(1);
                 }
             }
-            5 => {
+            Instruction::SkipEqReg { x, y } => {
                 // jump if registers are equal
-                if xreg == yreg {
+                if self.reg(x) == self.reg(y) {
                     self.skip_instructions(1);
                 }
             }
-            6 => {
+            Instruction::LoadImm { x, nn } => {
                 // set register vx
-                let value: u16 = instr & NN;
+                self.set_reg(x, nn);
+            }
+            Instruction::AddImm { x, nn } => {
+                // add value to register vx, carry flag is not affected
+                self.set_reg(x, self.reg(x).wrapping_add(nn));
+            }
+            Instruction::Move { x, y } => {
+                self.set_reg(x, self.reg(y));
+            }
+            Instruction::Or { x, y } => {
+                // bitwise or
+                self.set_reg(x, self.reg(x) | self.reg(y));
+                self.vf_reset();
+            }
+            Instruction::And { x, y } => {
+                // bitwise and
+                self.set_reg(x, self.reg(x) & self.reg(y));
+                self.vf_reset();
+            }
+            Instruction::Xor { x, y } => {
+                // bitwise xor
+                self.set_reg(x, self.reg(x) ^ self.reg(y));
+                self.vf_reset();
+            }
+            Instruction::AddReg { x, y } => {
+                // Add. Sets 1 to VF if overflow
+                let (value, overflow) = self.reg(x).overflowing_add(self.reg(y));
 
-                self.set_X_register_value(instr, value);
+                self.set_reg(x, value);
+                self.registers[0xf] = u8::from(overflow);
             }
-            7 => {
-                // add value to register vx
-                let value = instr & NN;
-                self.set_X_register_value(instr, (xreg + value) & NN);
+            Instruction::Sub { x, y } => {
+                // Subtract. Sets 0 to VF if underflow
+                let (value, underflow) = self.reg(x).overflowing_sub(self.reg(y));
+
+                self.set_reg(x, value);
+                self.registers[0xf] = u8::from(!underflow);
             }
-            8 => {
-                // binary ops
-                match instr & N {
-                    0x0000 => {
-                        let yreg = self.get_Y_register_value(instr);
+            Instruction::ShiftRight { x, y } => {
+                // Ambiguous shift
+                let value = self.shift_source(x, y);
 
-                        self.set_X_register_value(instr, yreg);
-                    }
-                    0x0001 => {
-                        // bitwise or
-                        let reg_y = self.registers[iyreg];
+                self.set_reg(x, value >> 1);
+                self.registers[0xf] = value & 0b00000001;
+            }
+            Instruction::SubN { x, y } => {
+                // Subtract. Sets 0 to VF if underflow
+                let (value, underflow) = self.reg(y).overflowing_sub(self.reg(x));
 
-                        self.registers[ixreg] |= reg_y;
-                    }
-                    0x0002 => {
-                        // bitwise and
-                        let reg_y = self.registers[iyreg];
-                        self.registers[ixreg] &= reg_y;
-                    }
-                    0x0003 => {
-                        // Logical or
-                        let reg_y = self.registers[iyreg];
-                        self.registers[ixreg] ^= reg_y;
-                    }
-                    0x0004 => {
-                        // Add. Also checks overflow. Sets 1 to VF if overflow
-                        let reg_y = u8_from_u16(self.get_Y_register_value(instr));
-                        let carry_flag_value: u8;
-
-                        match self.registers[ixreg].checked_add(reg_y) {
-                            Some(_) => {
-                                carry_flag_value = 0;
-                                // self.registers[0xf] = 0;
-                            }
-                            None => {
-                                carry_flag_value = 1;
-                                //self.registers[0xf] = 1;
-                            }
-                        }
-
-                        self.set_X_register_value(instr, (xreg + yreg) & 0xff);
-                        self.registers[0xf] = carry_flag_value;
-                    } // Subtract
-                    0x0005 => {
-                        let reg_y = self.registers[iyreg];
-
-                        match self.registers[ixreg].checked_sub(reg_y) {
-                            Some(v) => {
-                                self.registers[ixreg] = v;
-                                self.registers[0xf] = 1;
-                            }
-                            None => {
-                                let underflow_val = (0xff - (reg_y - self.registers[ixreg])) + 1;
-                                self.registers[ixreg] = underflow_val;
-
-                                self.registers[0xf] = 0;
-                            }
-                        }
-                    }
-                    0x0006 => {
-                        // Ambiguous shift
-                        self.set_X_register_value(instr, yreg >> 1);
-
-                        if 0b00000001 & yreg == 0b00000001 {
-                            self.registers[0xf] = 1;
-                        } else if 0b00000001 & yreg == 0b00000000 {
-                            self.registers[0xf] = 0;
-                        }
-                    }
-                    0x0007 => {
-                        // Subtract
-                        let reg_y = self.registers[iyreg];
-
-                        let reg_x = self.registers[ixreg];
-                        match reg_y.checked_sub(reg_x) {
-                            Some(v) => {
-                                self.registers[ixreg] = v;
-                                self.registers[0xf] = 1;
-                            }
-                            None => {
-                                // Underflow
-                                self.registers[ixreg] = (0xff - (reg_x - reg_y)) + 1;
-                                self.registers[0xf] = 0;
-                            }
-                        }
-                    }
-                    0x000e => {
-                        // Ambiguous shift
-                        self.set_X_register_value(instr, yreg << 1);
-
-                        if 0b10000000 & yreg == 0b10000000 {
-                            self.set_register_value(0xf, 1);
-                        } else if 0b10000000 & yreg == 0b00000000 {
-                            self.set_register_value(0xf, 0);
-                        }
-                    }
-                    _ => print_unknown_instr(instr),
-                }
+                self.set_reg(x, value);
+                self.registers[0xf] = u8::from(!underflow);
             }
-            9 => {
+            Instruction::ShiftLeft { x, y } => {
+                // Ambiguous shift
+                let value = self.shift_source(x, y);
+
+                self.set_reg(x, value << 1);
+                self.registers[0xf] = (value & 0b10000000) >> 7;
+            }
+            Instruction::SkipNeReg { x, y } => {
                 // jump if registers are unequal
-                if xreg != yreg {
+                if self.reg(x) != self.reg(y) {
                     self.skip_instructions(1);
                 }
             }
-            0xa => {
+            Instruction::LoadI { nnn } => {
                 // set index register i
-                let index = instr & NNN;
-                self.ireg = index;
+                self.ireg = nnn;
             }
-            0xb => {
-                // jump with offset from register v0
-                let jump_addr = (instr&NNN) + self.get_X_register_value(instr);
+            Instruction::JumpOffset { nnn } => {
+                // jump with offset from v0, or from vx where x is the top nibble of nnn
+                let x = if self.quirks.jump_vx {
+                    u8::try_from(nnn >> 8).unwrap()
+                } else {
+                    0
+                };
+                let jump_addr = nnn + u16::from(self.reg(x));
                 self.ip = usize_from_u16(jump_addr);
             }
-            0xc => {
-                let val = instr & NN;
-
-                let mut rng = rand::thread_rng();
-                let rand_num: u16 = (rng.gen::<u16>() & NN) & val;
-
-                self.set_X_register_value(instr, rand_num);
-            }
-            0xd => {
-                // display_draw
-                let height = instr & N;
+            Instruction::Random { x, nn } => {
+                let rand_num: u8 = self.rng.gen::<u8>() & nn;
 
-                self.draw_instr(xreg, yreg, height);
-                self.draw();
+                self.set_reg(x, rand_num);
             }
-            0xe => {
-                // Skip if key
-                match instr & NN {
-                    0x009e => {
-                        // Skip if key is down
-                        if self.input_mut().unwrap().key_pad[ixreg] {
-                            self.ip += 2;
-                        }
-                    }
-                    0x00a1 => {
-                        // Skip if key is not down
-                        if !self.input_mut().unwrap().key_pad[ixreg] {
-                            self.ip += 2;
-                        }
-                    }
-                    _ => print_unknown_instr(instr),
+            Instruction::Draw { x, y, n } => {
+                // XOR the sprite at I onto the screen, VF is set on collision
+                let (xpos, ypos) = (self.reg(x), self.reg(y));
+                self.cover(Access::Read, self.ireg, usize::from(n));
+                let ireg = usize_from_u16(self.ireg);
+                let sprite: Vec<u8> = (0..usize::from(n)).map(|i| self.mem(ireg + i)).collect();
+
+                let collision = self
+                    .screen
+                    .draw_sprite(xpos, ypos, &sprite, self.quirks.clipping);
+                self.registers[0xf] = u8::from(collision);
+                self.vblank_wait = self.quirks.display_wait;
+
+                if let Some(profiler) = self.profiler.as_mut() {
+                    profiler.record_draw();
                 }
             }
-            0xf => match instr & NN {
-                0x0007 => {
-                    let delay_timer: u16 = self.get_delay_timer();
-                    self.set_X_register_value(instr, delay_timer)
-                }
-                0x0015 => {
-                    self.delay_timer = self.get_X_register_value(instr);
-                }
-                0x0018 => {
-                    self.sound_timer = self.get_X_register_value(instr);
-                }
-                0x001e => {
-                    let xreg = self.get_X_register_value(instr);
-                    self.ireg += xreg;
+            Instruction::SkipKey { x } => {
+                // Skip if key is down
+                let key = usize::from(self.reg(x) & 0xf);
+                if self.key_pad[key] {
+                    self.skip_instructions(1);
                 }
-                0x0033 => {
-                    let ones = xreg % 10;
-                    let tens = ((xreg - ones) % 100) / 10;
-                    let houndreds = ((xreg - tens - ones) % 1000) / 100;
-
-                    self.rom_bytes[usize_from_u16(self.ireg)] = u8::try_from(houndreds).unwrap();
-                    self.rom_bytes[usize_from_u16(self.ireg) + 1] = u8::try_from(tens).unwrap();
-                    self.rom_bytes[usize_from_u16(self.ireg) + 2] = u8::try_from(ones).unwrap();
+            }
+            Instruction::SkipNotKey { x } => {
+                // Skip if key is not down
+                let key = usize::from(self.reg(x) & 0xf);
+                if !self.key_pad[key] {
+                    self.skip_instructions(1);
                 }
-                0x0055 => {
-                    for i in 0..(ixreg + 1) {
-                        self.rom_bytes[usize::try_from(self.ireg).unwrap() + i] = self.registers[i];
+            }
+            Instruction::WaitKey { x } => {
+                // Block until a key is pressed and released again, like the
+                // COSMAC VIP. Keys held before FX0A started do not count.
+                match self.key_wait {
+                    Some(key) if !self.key_pad[usize::from(key)] => {
+                        self.set_reg(x, key);
+                        self.key_wait = None;
                     }
-                }
-                0x0065 => {
-                    for i in 0..(ixreg + 1) {
-                        self.registers[i] = self.rom_bytes[usize::try_from(self.ireg).unwrap() + i];
+                    Some(_) => self.ip -= 2,
+                    None => {
+                        self.key_wait = (0..16).find(|k| {
+                            self.key_pad[usize::from(*k)] && !self.prev_key_pad[usize::from(*k)]
+                        });
+                        self.ip -= 2;
                     }
                 }
-                _ => print_unknown_instr(instr),
-            },
-            _ => print_unknown_instr(instr),
+            }
+            Instruction::GetDelay { x } => {
+                let delay_timer = self.get_delay_timer();
+                self.set_reg(x, u8::try_from(delay_timer).unwrap_or(u8::MAX));
+            }
+            Instruction::SetDelay { x } => {
+                self.delay_timer = u16::from(self.reg(x));
+            }
+            Instruction::SetSound { x } => {
+                self.sound_timer = u16::from(self.reg(x));
+            }
+            Instruction::AddI { x } => {
+                self.ireg = self.ireg.wrapping_add(u16::from(self.reg(x)));
+            }
+            Instruction::Font { x } => {
+                self.ireg = (FONT_ADDR + usize::from(self.reg(x) & 0xf) * 5) as u16;
+            }
+            Instruction::Bcd { x } => {
+                let value = self.reg(x);
+                self.cover(Access::Written, self.ireg, 3);
+                let ireg = usize_from_u16(self.ireg);
+
+                *self.mem_mut(ireg) = value / 100;
+                *self.mem_mut(ireg + 1) = (value / 10) % 10;
+                *self.mem_mut(ireg + 2) = value % 10;
+            }
+            Instruction::Store { x } => {
+                self.cover(Access::Written, self.ireg, usize::from(x) + 1);
+                let ireg = usize_from_u16(self.ireg);
+                for i in 0..=usize::from(x) {
+                    *self.mem_mut(ireg + i) = self.registers[i];
+                }
+                self.memory_increment(x);
+            }
+            Instruction::Load { x } => {
+                self.cover(Access::Read, self.ireg, usize::from(x) + 1);
+                let ireg = usize_from_u16(self.ireg);
+                for i in 0..=usize::from(x) {
+                    self.registers[i] = self.mem(ireg + i);
+                }
+                self.memory_increment(x);
+            }
+            _ => {
+                self.raise(Fault::UnknownInstruction {
+                    pc: self.current_pc(),
+                    opcode: instr,
+                });
+            }
         }
     }
 
     fn get_instruction(&mut self) -> u16 {
         let mut value: u16 = 0;
         let ip = self.ip;
-        value += u16::try_from(self.rom_bytes[ip]).unwrap() << 8;
-        value += u16::try_from(self.rom_bytes[ip + 1]).unwrap();
-        return value;
+        value += u16::from(self.mem(ip)) << 8;
+        value += u16::from(self.mem(ip + 1));
+        value
     }
 
-    fn get_register_value(&mut self, ireg: usize) -> u16 {
-        u16::try_from(self.registers[ireg]).unwrap()
+    /// Addresses past the end of memory wrap around to 0
+    fn mem(&self, addr: usize) -> u8 {
+        self.rom_bytes[addr % self.rom_bytes.len()]
     }
 
-    fn set_register_value(&mut self, ireg: usize, value: u16) {
-        self.registers[ireg] = u8_from_u16(value & NN);
+    fn mem_mut(&mut self, addr: usize) -> &mut u8 {
+        let len = self.rom_bytes.len();
+        &mut self.rom_bytes[addr % len]
     }
 
-    fn get_X_register_value(&mut self, instr: u16) -> u16 {
-        u16::try_from(self.registers[usize::try_from((instr & X) >> 8).unwrap()]).unwrap()
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[0xf] = 0;
+        }
     }
 
-    fn get_Y_register_value(&mut self, instr: u16) -> u16 {
-        u16::try_from(self.registers[usize::try_from((instr & Y) >> 4).unwrap()]).unwrap()
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_vx {
+            self.reg(x)
+        } else {
+            self.reg(y)
+        }
     }
 
-    fn set_X_register_value(&mut self, instr: u16, val: u16) {
-        self.registers[usize::try_from((instr & X) >> 8).unwrap()] =
-            u8::try_from(val & NN).unwrap();
+    fn memory_increment(&mut self, x: u8) {
+        if self.quirks.memory_increment {
+            self.ireg = self.ireg.wrapping_add(u16::from(x) + 1);
+        }
     }
 
-    fn set_Y_register_value(&mut self, instr: u16, val: u16) {
-        self.registers[usize::try_from((instr & Y) >> 4).unwrap()] =
-            u8::try_from(val & NN).unwrap();
+    fn reg(&self, ireg: u8) -> u8 {
+        self.registers[usize::from(ireg)]
     }
 
-    fn register_equal(&mut self, ireg: usize, val: u16) -> bool {
-        self.registers[ireg] == u8::try_from(val).unwrap()
+    fn set_reg(&mut self, ireg: u8, value: u8) {
+        self.registers[usize::from(ireg)] = value;
     }
 }
 
-fn get_X(instr: u16) -> usize {
-    usize::try_from((instr & X) >> 8).unwrap()
-}
-
-fn get_Y(instr: u16) -> usize {
-    usize::try_from((instr & Y) >> 4).unwrap()
-}
-
 fn u16_from_usize(val: usize) -> u16 {
     u16::try_from(val).unwrap()
 }
 
-fn u16_from_u8(val: u8) -> u16 {
-    u16::try_from(val).unwrap()
-}
-
-fn u8_from_u16(value: u16) -> u8 {
-    u8::try_from(value).unwrap()
-}
-
 fn usize_from_u16(val: u16) -> usize {
-    usize::try_from(val).unwrap()
+    usize::from(val)
 }
 
-fn print_u16_hex(val: u16) {
-    println!("{:#06x}", val);
-}
-
-fn print_unknown_instr(instr: u16) {
-    println!("Unknown instruction {:#06x}", instr);
-}
+/// 4K of memory with the font at 0x050 and the ROM at 0x200. Memory grows
+/// for larger ROMs, up to the 64K I can address.
+fn load_rom(rom: &[u8]) -> Vec<u8> {
+    let rom = &rom[..rom.len().min(0x10000 - ADDR_OFFSET)];
+    let mut memory = vec![0; MEMORY_SIZE.max(ADDR_OFFSET + rom.len())];
 
-fn read_rom(name: &str) -> Vec<u8> {
-    let raw_rom = fs::read(name).expect("Error reading rom file!");
+    memory[FONT_ADDR..FONT_ADDR + FONT.len()].copy_from_slice(&FONT);
+    memory[ADDR_OFFSET..ADDR_OFFSET + rom.len()].copy_from_slice(rom);
 
-    let mut form_rom = Vec::new();
-
-    // Add 0x200 of empty space
-    for _ in 0..ADDR_OFFSET {
-        form_rom.push(0);
-    }
-
-    for i in raw_rom {
-        form_rom.push(i);
-    }
-
-    form_rom
+    memory
 }
 
 pub fn get_current_millis() -> u128 {
@@ -510,371 +690,160 @@ pub fn get_current_millis() -> u128 {
     since_the_epoch.as_millis()
 }
 
-pub fn main_chip_loop(rom_name: &str, instr_per_secs: f32) {
-    let mut cpu: Chip8 = Chip8::new(rom_name);
-
-    let mut last_time: u128 = get_current_millis();
-    let hz60: u128 = 1000 / 60;
-
-    'mainloop: loop {
-        // Change all keycode values to false
-        if InputAction::BreakDisplay == cpu.input_mut().unwrap().handle_input() {
-            break 'mainloop;
-        }
-
-        let current_millis = get_current_millis();
-        if current_millis - last_time >= hz60 {
-            cpu.decrement_timers();
-            last_time = current_millis;
-        }
-
-        let instr = cpu.fetch();
-        cpu.decode(instr);
-
-        std::thread::sleep(Duration::from_secs_f32(1.0 / instr_per_secs));
-    }
+/// Spreads an instruction rate over 60 Hz frames. Fractions carry over so
+/// low speeds still run on average.
+pub struct FrameBudget {
+    per_frame: f32,
+    carry: f32,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    // Tools for testing
-    fn u16_to_u8(instr: u16) -> Vec<u8> {
-        let pair2 = u8::try_from(instr & 0xff).unwrap();
-        let pair1 = u8::try_from((instr & 0xff00) >> 8).unwrap();
-
-        let mut ret: Vec<u8> = Vec::new();
-
-        ret.push(pair1);
-        ret.push(pair2);
-
-        ret
-    }
-
-    fn vec_u16_to_u8(instrs: Vec<u16>) -> Vec<u8> {
-        let mut ret: Vec<u8> = Vec::new();
-
-        for instr in instrs {
-            let mut instr_u8 = u16_to_u8(instr);
-            ret.append(&mut instr_u8);
-        }
-
-        ret
-    }
-
-    fn pad_u16_to_u8(instr: u16) -> Vec<u8> {
-        let mut ret: Vec<u8> = u16_to_u8(instr);
-        pad_u8_vec(&mut ret);
-
-        ret
-    }
-
-    fn pad_u8_vec(v: &mut Vec<u8>) {
-        for _ in 0..512 {
-            v.insert(0, 0);
+impl FrameBudget {
+    pub fn new(instr_per_secs: f32) -> Self {
+        Self {
+            per_frame: instr_per_secs / 60.0,
+            carry: 0.0,
         }
     }
 
-    // Now to the tests!
-
-    #[test]
-    fn test_u16_conversion_to_u8() {
-        let ret = u16_to_u8(0xfafb);
-
-        assert_eq!(ret[0], 0xfa);
-        assert_eq!(ret[1], 0xfb);
-    }
-
-    #[test]
-    fn test_vec_u16_to_u8() {
-        let mut v: Vec<u16> = Vec::new();
-        v.push(0x1234);
-        v.push(0x4321);
-
-        let ret = vec_u16_to_u8(v);
-
-        assert_eq!(ret[0], 0x12);
-        assert_eq!(ret[1], 0x34);
-        assert_eq!(ret[2], 0x43);
-        assert_eq!(ret[3], 0x21);
-    }
-
-    // Test instructions
-    #[test]
-    fn OC_1NNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x1123);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-        println!("{}", chip.ip);
-
-        assert_eq!(chip.ip, 0x123);
-    }
-
-    #[test]
-    fn OC_3XNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x3011);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x11;
-        let ip_before = chip.ip;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.ip, ip_before + 4);
-    }
-
-    #[test]
-    fn OC_4XNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x4011);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x12;
-        let ip_before = chip.ip;
+    /// Instructions to run in the next frame
+    pub fn take_frame(&mut self) -> u32 {
+        self.carry += self.per_frame;
+        let instructions = self.carry as u32;
+        self.carry -= instructions as f32;
 
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.ip, ip_before + 4);
+        instructions
     }
+}
 
-    #[test]
-    fn OC_5XY0() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x5010);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x12;
-        chip.registers[1] = 0x12;
-        let ip_before = chip.ip;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.ip, ip_before + 4);
-    }
-
-    #[test]
-    fn OC_9XY0() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x9010);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x12;
-        chip.registers[1] = 0x13;
-        let ip_before = chip.ip;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.ip, ip_before + 4);
-    }
-
-    #[test]
-    fn OC_6XNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x6111);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[1], 0x11);
-    }
-
-    #[test]
-    fn OC_7XNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x7111);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[1], 0x11);
-    }
-
-    #[test]
-    fn OC_8XY0() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8010);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[1], 0x11);
-    }
-
-    #[test]
-    fn OC_8XY1() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8011);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x18;
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[0], 0x18 | 0x11);
-    }
-
-    #[test]
-    fn OC_8XY2() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8012);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x18;
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[0], 0x18 & 0x11);
-    }
-
-    #[test]
-    fn OC_8XY3() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8013);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x18;
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
+pub struct RunOptions {
+    pub instr_per_secs: f32,
+    pub tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub callgraph: Option<CallGraph>,
+    pub coverage: Option<Coverage>,
+    pub quirks: Quirks,
+    pub seed: Option<u64>,
+    /// Records the key pad into `Chip8::recording`
+    pub record: bool,
+    /// Takes the key pad from this movie until it ends, then from the keyboard
+    pub replay: Option<Movie>,
+    /// Where F12 saves screenshots and F9 captures
+    pub screenshot_dir: PathBuf,
+    /// Captures every frame to this GIF, and the audio next to it as WAV
+    pub gif: Option<PathBuf>,
+    /// Streams every frame as raw RGB24 to stdout
+    pub raw_frames: bool,
+    pub persistence: Persistence,
+    pub palette: Palette,
+    /// Palettes the palette hotkey cycles through
+    pub palettes: Vec<Palette>,
+    pub rotation: Rotation,
+    /// Scale the screen by whole numbers only
+    pub integer_scaling: bool,
+    /// Start in borderless fullscreen
+    pub fullscreen: bool,
+    pub filters: Vec<Filter>,
+    /// Reload the ROM when it or its `.8o` source changes
+    pub watch: bool,
+}
 
-        assert_eq!(chip.registers[0], 0x18 ^ 0x11);
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            instr_per_secs: 100.0,
+            tracer: None,
+            profiler: None,
+            callgraph: None,
+            coverage: None,
+            quirks: Quirks::default(),
+            seed: None,
+            record: false,
+            replay: None,
+            screenshot_dir: PathBuf::from("."),
+            gif: None,
+            raw_frames: false,
+            persistence: Persistence::Off,
+            palette: Palette::default(),
+            palettes: Vec::new(),
+            rotation: Rotation::None,
+            integer_scaling: false,
+            fullscreen: false,
+            filters: Vec::new(),
+            watch: false,
+        }
     }
+}
 
-    #[test]
-    fn OC_8XY4() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8014);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x18;
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        assert_eq!(chip.registers[0], 0x18 + 0x11);
-    }
+    // Single instructions are covered by the JSON vectors in tests/vectors
 
     #[test]
-    fn OC_8XY5() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8015);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
+    fn wait_key_needs_a_new_press_and_release() {
+        // LD V2, K then JP 0x202
+        let mut chip = Chip8::from_rom(&[0xf2, 0x0a, 0x12, 0x02], Quirks::default());
+        let frame = |chip: &mut Chip8, keys: &[usize]| {
+            chip.key_pad = [false; 16];
+            for key in keys {
+                chip.key_pad[*key] = true;
+            }
+            chip.run_frame(10);
         };
 
-        chip.registers[0] = 0x18;
-        chip.registers[1] = 0x11;
+        // Held from before the wait started, so its release does not count
+        chip.key_pad[4] = true;
+        chip.prev_key_pad[4] = true;
+        frame(&mut chip, &[4]);
+        frame(&mut chip, &[]);
+        assert_eq!(chip.pc(), 0x200);
 
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[0], 0x18 - 0x11);
+        frame(&mut chip, &[7]);
+        assert_eq!(chip.pc(), 0x200);
+        frame(&mut chip, &[]);
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.registers()[2], 7);
     }
 
     #[test]
-    fn OC_8XY7() {
-        let rom: Vec<u8> = pad_u16_to_u8(0x8017);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[0] = 0x11;
-        chip.registers[1] = 0x18;
+    fn sys_calls_are_ignored() {
+        // SYS 0x123 then JP 0x202
+        let mut chip = Chip8::from_rom(&[0x01, 0x23, 0x12, 0x02], Quirks::default());
+        chip.run_frame(5);
 
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.registers[0], 0x18 - 0x11);
+        assert_eq!(chip.fault, None);
+        assert!(chip.halted());
     }
 
     #[test]
-    fn OC_ANNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0xa123);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        let instr = chip.fetch();
-        chip.decode(instr);
+    fn coverage_wraps_like_memory() {
+        // LD I, 0xFFF then LD [I], V1 then JP 0x204
+        let mut chip = Chip8::from_rom(&[0xaf, 0xff, 0xf1, 0x55, 0x12, 0x04], Quirks::default());
+        chip.coverage = Some(Coverage::new());
+        chip.run_frame(5);
 
-        assert_eq!(chip.ireg, 0x123);
+        let coverage = chip.coverage.as_ref().unwrap();
+        assert!(coverage.has(0xfff, Access::Written));
+        assert!(coverage.has(0x000, Access::Written));
+        assert!(!coverage.has(0x1000, Access::Written));
     }
 
     #[test]
-    fn OC_BXNN() {
-        let rom: Vec<u8> = pad_u16_to_u8(0xb123);
-        let mut chip = Chip8 {
-            rom_bytes: rom,
-            externals: None,
-            ..Default::default()
-        };
-
-        chip.registers[1] = 0x11;
-
-        let instr = chip.fetch();
-        chip.decode(instr);
-
-        assert_eq!(chip.ip, 0x11 + 0x123);
+    fn reset_starts_a_new_rom_with_the_same_seed() {
+        // LD V1, 0x11 then JP 0x202
+        let mut chip = Chip8::from_rom(&[0x61, 0x11, 0x12, 0x02], Quirks::default());
+        chip.set_seed(42);
+        chip.profiler = Some(Profiler::new());
+        chip.run_frame(5);
+        assert_eq!(chip.registers()[1], 0x11);
+
+        // LD V2, 0x22
+        chip.reset(&[0x62, 0x22]);
+        assert_eq!((chip.pc(), chip.frame, chip.registers()[1]), (0x200, 0, 0));
+        assert_eq!(chip.seed(), 42);
+        assert_eq!(&chip.memory()[0x200..0x204], &[0x62, 0x22, 0x00, 0x00]);
+        assert_eq!(chip.rom(), &[0x62, 0x22]);
+        assert_eq!(chip.profiler.as_ref().map(Profiler::total), Some(0));
     }
 }
@@ -0,0 +1,227 @@
+//! User settings from a TOML file, `$XDG_CONFIG_HOME/chip8/config.toml` or
+//! `~/.config/chip8/config.toml` unless given with `--config`:
+//!
+//! ```toml
+//! palette = "vip"
+//! rom_dir = "~/chip8"
+//!
+//! [palettes.sunset]
+//! colors = ["#1a0a2e", "#ff7b54", "#ffb26b", "#ffd56f"]
+//!
+//! [roms."br8kout.ch8"]
+//! palette = "lcd"
+//!
+//! [roms."vertical.ch8"]
+//! rotation = 90
+//! title = "Vertical Shooter"
+//! platform = "xochip"
+//! ```
+//!
+//! ROM sections are looked up by file name and win over the global settings.
+//! Command line options win over both.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::palette::{self, Palette};
+use crate::quirks;
+use crate::screen::Rotation;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub palette: Option<String>,
+    /// Where the ROM menu looks for ROMs, `roms` by default
+    pub rom_dir: Option<PathBuf>,
+    pub palettes: BTreeMap<String, PaletteConfig>,
+    pub roms: BTreeMap<String, RomConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaletteConfig {
+    /// `#rrggbb` colors, background first
+    pub colors: Vec<String>,
+}
+
+/// Settings for a single ROM
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub palette: Option<String>,
+    /// Clockwise screen rotation in degrees
+    pub rotation: Option<Rotation>,
+    /// Name shown in the ROM menu
+    pub title: Option<String>,
+    /// One of `quirks::PROFILES`, for the ROM menu
+    pub platform: Option<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.palettes()?;
+        for (name, rom) in &config.roms {
+            if let Some(platform) = &rom.platform {
+                if !quirks::PROFILES.contains(&platform.as_str()) {
+                    return Err(format!("unknown platform '{}' for {}", platform, name));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads `path`, a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(dir.join("chip8").join("config.toml"))
+    }
+
+    /// The ROM menu's directory, with a leading `~` for the home directory
+    pub fn rom_dir(&self) -> PathBuf {
+        let Some(dir) = &self.rom_dir else {
+            return PathBuf::from("roms");
+        };
+        match (dir.strip_prefix("~"), env::var_os("HOME")) {
+            (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+            _ => dir.clone(),
+        }
+    }
+
+    /// Settings for the ROM at `rom_path`
+    pub fn rom(&self, rom_path: &str) -> Option<&RomConfig> {
+        let name = Path::new(rom_path).file_name()?.to_str()?;
+        self.roms.get(name)
+    }
+
+    /// Built-in themes followed by the custom palettes, in the order the
+    /// palette hotkey cycles through them. Custom palettes replace themes of
+    /// the same name.
+    pub fn palettes(&self) -> Result<Vec<Palette>, String> {
+        let mut palettes: Vec<Palette> = palette::THEMES
+            .iter()
+            .filter(|name| !self.palettes.contains_key(**name))
+            .filter_map(|name| Palette::theme(name))
+            .collect();
+
+        for (name, custom) in &self.palettes {
+            palettes.push(Palette::parse(name, &custom.colors)?);
+        }
+
+        Ok(palettes)
+    }
+
+    /// The palette for `rom_path`: `name` if given, else the ROM's setting,
+    /// else the global one, else the default theme
+    pub fn palette_for(&self, rom_path: &str, name: Option<&str>) -> Result<Palette, String> {
+        let name = name
+            .or_else(|| self.rom(rom_path)?.palette.as_deref())
+            .or(self.palette.as_deref());
+        let Some(name) = name else {
+            return Ok(Palette::default());
+        };
+
+        self.palettes()?
+            .into_iter()
+            .find(|palette| palette.name == name)
+            .ok_or_else(|| format!("unknown palette '{}'", name))
+    }
+
+    /// `rotation` if given, else the ROM's setting
+    pub fn rotation_for(&self, rom_path: &str, rotation: Option<Rotation>) -> Rotation {
+        rotation
+            .or_else(|| self.rom(rom_path)?.rotation)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r##"
+        palette = "vip"
+
+        [palettes.sunset]
+        colors = ["#1a0a2e", "#ff7b54"]
+
+        [roms."br8kout.ch8"]
+        palette = "sunset"
+        rotation = 270
+    "##;
+
+    #[test]
+    fn palette_lookup_order() {
+        let config = Config::parse(CONFIG).unwrap();
+        let name = |rom: &str, cli: Option<&str>| config.palette_for(rom, cli).unwrap().name;
+
+        assert_eq!(name("roms/br8kout.ch8", None), "sunset");
+        assert_eq!(name("roms/br8kout.ch8", Some("lcd")), "lcd");
+        assert_eq!(name("roms/ibm.ch8", None), "vip");
+        assert_eq!(
+            Config::default().palette_for("ibm.ch8", None).unwrap().name,
+            "amber"
+        );
+        assert_eq!(
+            config.palette_for("ibm.ch8", Some("pink")),
+            Err("unknown palette 'pink'".to_string())
+        );
+    }
+
+    #[test]
+    fn custom_palettes_follow_the_themes() {
+        let config = Config::parse(CONFIG).unwrap();
+        let names: Vec<String> = config
+            .palettes()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+
+        assert_eq!(names.len(), palette::THEMES.len() + 1);
+        assert_eq!(names.last().unwrap(), "sunset");
+    }
+
+    #[test]
+    fn rotation_lookup_order() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        assert_eq!(config.rotation_for("br8kout.ch8", None), Rotation::Cw270);
+        assert_eq!(
+            config.rotation_for("br8kout.ch8", Some(Rotation::None)),
+            Rotation::None
+        );
+        assert_eq!(config.rotation_for("ibm.ch8", None), Rotation::None);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(Config::parse("colour = 1").is_err());
+        assert_eq!(
+            Config::parse("[palettes.x]\ncolors = [\"#000000\", \"red\"]").unwrap_err(),
+            "invalid color 'red'"
+        );
+        assert!(Config::parse("[roms.\"a.ch8\"]\nrotation = 45").is_err());
+        assert_eq!(
+            Config::parse("[roms.\"a.ch8\"]\nplatform = \"nes\"").unwrap_err(),
+            "unknown platform 'nes' for a.ch8"
+        );
+    }
+}
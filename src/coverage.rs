@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+
+use crate::disasm;
+
+const ADDR_OFFSET: usize = 0x200;
+
+/// How a memory byte was used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Fetched as part of an instruction
+    Executed = 1,
+    /// Read as data, e.g. sprites or FX65
+    Read = 2,
+    /// Written by FX33 or FX55
+    Written = 4,
+}
+
+/// Records per memory byte whether it was executed, read or written.
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            flags: vec![0; 0x10000],
+        }
+    }
+
+    /// Addresses past 0xFFFF wrap around to 0
+    pub fn mark(&mut self, access: Access, addr: usize, len: usize) {
+        let size = self.flags.len();
+        for addr in addr..addr + len {
+            self.flags[addr % size] |= access as u8;
+        }
+    }
+
+    pub fn has(&self, addr: usize, access: Access) -> bool {
+        self.flags.get(addr).is_some_and(|f| f & access as u8 != 0)
+    }
+
+    /// Percentages over the ROM image, which is loaded at 0x200.
+    pub fn summary(&self, rom_len: usize) -> String {
+        let range = ADDR_OFFSET..ADDR_OFFSET + rom_len;
+        let count = |access: Access| range.clone().filter(|a| self.has(*a, access)).count();
+        let untouched = range
+            .clone()
+            .filter(|a| self.flags.get(*a) == Some(&0))
+            .count();
+
+        let line = |name: &str, n: usize| {
+            let percent = if rom_len == 0 {
+                0.0
+            } else {
+                n as f64 * 100.0 / rom_len as f64
+            };
+            format!("{:<14} {:>6} bytes {:>6.1}%\n", name, n, percent)
+        };
+
+        let mut out = format!("rom            {:>6} bytes\n", rom_len);
+        out.push_str(&line("executed", count(Access::Executed)));
+        out.push_str(&line("read as data", count(Access::Read)));
+        out.push_str(&line("written", count(Access::Written)));
+        out.push_str(&line("never touched", untouched));
+        out
+    }
+
+    /// Disassembly of the ROM with an `XRW` column per line. A flag is set
+    /// when any byte of the line was accessed that way.
+    pub fn listing(&self, rom: &[u8]) -> String {
+        let lines = disasm::decode_rom(rom);
+        let labels = disasm::auto_labels(&lines);
+        let mut out = String::new();
+
+        for line in &lines {
+            if let Some(label) = labels.get(&line.addr) {
+                out.push_str(&format!("{}:\n", label));
+            }
+
+            let addrs = usize::from(line.addr)..usize::from(line.addr) + line.bytes.len();
+            let flag = |access: Access, c: char| {
+                if addrs.clone().any(|a| self.has(a, access)) {
+                    c
+                } else {
+                    '-'
+                }
+            };
+
+            let raw: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text = match line.instr {
+                Some(instr) => disasm::classic(&instr, line.long, &labels),
+                None => format!("DB {:#04X}", line.bytes[0]),
+            };
+
+            out.push_str(&format!(
+                "{}{}{} {:#06X}  {:<11} {}\n",
+                flag(Access::Executed, 'X'),
+                flag(Access::Read, 'R'),
+                flag(Access::Written, 'W'),
+                line.addr,
+                raw.join(" "),
+                text
+            ));
+        }
+
+        out
+    }
+
+    pub fn write_listing(&self, path: &str, rom: &[u8]) -> io::Result<()> {
+        fs::write(
+            path,
+            format!("{}\n{}", self.summary(rom.len()), self.listing(rom)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn marks_accumulate() {
+        let mut coverage = Coverage::new();
+
+        coverage.mark(Access::Executed, 0x200, 2);
+        coverage.mark(Access::Read, 0x201, 2);
+        coverage.mark(Access::Written, 0xffff, 4);
+
+        assert!(coverage.has(0x201, Access::Executed));
+        assert!(coverage.has(0x201, Access::Read));
+        assert!(!coverage.has(0x203, Access::Read));
+        assert!(coverage.has(0xffff, Access::Written));
+        assert!(coverage.has(0x0002, Access::Written));
+        assert!(!coverage.has(0x0003, Access::Written));
+    }
+
+    #[test]
+    fn summary_and_listing() {
+        // CLS, LD I 0x206, DRW, sprite byte 0xff
+        let rom = [0x00, 0xe0, 0xa2, 0x06, 0xd0, 0x01, 0xff, 0x00];
+        let mut coverage = Coverage::new();
+        coverage.mark(Access::Executed, 0x200, 6);
+        coverage.mark(Access::Read, 0x206, 1);
+
+        let summary = coverage.summary(rom.len());
+        assert!(summary.contains("executed            6 bytes   75.0%"));
+        assert!(summary.contains("read as data        1 bytes   12.5%"));
+        assert!(summary.contains("never touched       1 bytes   12.5%"));
+
+        let listing = coverage.listing(&rom);
+        assert!(listing.contains("X-- 0x0200  00 E0       CLS\n"));
+        assert!(listing.contains("-R- 0x0206  FF 00       DW 0xFF00\n"));
+    }
+}
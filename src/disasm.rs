@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use crate::instruction::Instruction;
+use crate::symbols::SymbolMap;
+
+const ADDR_OFFSET: u16 = 0x200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// Cowgod style mnemonics, e.g. `LD V1, 0x11`
+    Classic,
+    /// Octo assembly, e.g. `v1 := 0x11`
+    Octo,
+}
+
+/// One decoded line of a ROM listing.
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// `None` for a trailing odd byte that cannot form an instruction.
+    pub instr: Option<Instruction>,
+    /// Immediate of the 4-byte `F000 NNNN` form.
+    pub long: Option<u16>,
+}
+
+/// Linear sweep over the ROM, starting at 0x200.
+pub fn decode_rom(rom: &[u8]) -> Vec<Line> {
+    decode_from(rom, ADDR_OFFSET)
+}
+
+/// Linear sweep over `rom`, which is loaded at `base`.
+pub fn decode_from(rom: &[u8], base: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let addr = base.wrapping_add(offset as u16);
+
+        if offset + 1 >= rom.len() {
+            lines.push(Line {
+                addr,
+                bytes: vec![rom[offset]],
+                instr: None,
+                long: None,
+            });
+            break;
+        }
+
+        let opcode = u16::from(rom[offset]) << 8 | u16::from(rom[offset + 1]);
+        let mut instr = Instruction::decode(opcode);
+        let mut long = None;
+
+        if instr.size() == 4 {
+            if offset + 3 < rom.len() {
+                long = Some(u16::from(rom[offset + 2]) << 8 | u16::from(rom[offset + 3]));
+            } else {
+                // Truncated long load, show it as data
+                instr = Instruction::Unknown(opcode);
+            }
+        }
+
+        let size = instr.size();
+        lines.push(Line {
+            addr,
+            bytes: rom[offset..offset + size].to_vec(),
+            instr: Some(instr),
+            long,
+        });
+        offset += size;
+    }
+
+    lines
+}
+
+/// Generates `sub_XXX` labels for call targets and `LXXX` labels for jump
+/// targets that land on the start of a decoded instruction.
+pub fn auto_labels(lines: &[Line]) -> BTreeMap<u16, String> {
+    let starts: Vec<u16> = lines.iter().map(|line| line.addr).collect();
+    let mut labels = BTreeMap::new();
+
+    for line in lines {
+        let (target, is_call) = match line.instr {
+            Some(Instruction::Call { nnn }) => (nnn, true),
+            Some(Instruction::Jump { nnn }) => (nnn, false),
+            _ => continue,
+        };
+
+        if starts.binary_search(&target).is_err() {
+            continue;
+        }
+
+        let name = if is_call {
+            format!("sub_{:03X}", target)
+        } else {
+            format!("L{:03X}", target)
+        };
+
+        // A call label wins over a jump label for the same address
+        if is_call || !labels.contains_key(&target) {
+            labels.insert(target, name);
+        }
+    }
+
+    labels
+}
+
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    disassemble_with_symbols(rom, syntax, &SymbolMap::new())
+}
+
+/// Like `disassemble`, but names from an assembler symbol map take
+/// precedence over generated labels.
+pub fn disassemble_with_symbols(rom: &[u8], syntax: Syntax, symbols: &SymbolMap) -> String {
+    let lines = decode_rom(rom);
+    let labels = labels(&lines, symbols);
+
+    render(&lines, &labels, syntax)
+}
+
+/// Generated labels merged with the symbols that land on an instruction start.
+pub fn labels(lines: &[Line], symbols: &SymbolMap) -> BTreeMap<u16, String> {
+    let mut labels = auto_labels(lines);
+
+    for line in lines {
+        if let Some(name) = symbols.get(&line.addr) {
+            labels.insert(line.addr, name.clone());
+        }
+    }
+
+    labels
+}
+
+pub fn render(lines: &[Line], labels: &BTreeMap<u16, String>, syntax: Syntax) -> String {
+    let mut out = String::new();
+
+    for line in lines {
+        if let Some(label) = labels.get(&line.addr) {
+            match syntax {
+                Syntax::Classic => out.push_str(&format!("{}:\n", label)),
+                Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+            }
+        }
+
+        let raw: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let raw = raw.join(" ");
+
+        match syntax {
+            Syntax::Classic => {
+                let text = match line.instr {
+                    Some(instr) => classic(&instr, line.long, labels),
+                    None => format!("DB {:#04X}", line.bytes[0]),
+                };
+                out.push_str(&format!("    {:#06X}  {:<11} {}\n", line.addr, raw, text));
+            }
+            Syntax::Octo => {
+                let text = match line.instr {
+                    Some(instr) => octo(&instr, line.long, labels),
+                    None => format!("{:#04X}", line.bytes[0]),
+                };
+                out.push_str(&format!("    {:<24} # {:#06X}  {}\n", text, line.addr, raw));
+            }
+        }
+    }
+
+    out
+}
+
+fn addr(nnn: u16, labels: &BTreeMap<u16, String>) -> String {
+    match labels.get(&nnn) {
+        Some(label) => label.clone(),
+        None => format!("{:#05X}", nnn),
+    }
+}
+
+fn raw_bytes(opcode: u16) -> String {
+    format!("{:#04X} {:#04X}", opcode >> 8, opcode & 0xff)
+}
+
+pub fn classic(instr: &Instruction, long: Option<u16>, labels: &BTreeMap<u16, String>) -> String {
+    match *instr {
+        Instruction::Jump { nnn } => format!("JP {}", addr(nnn, labels)),
+        Instruction::Call { nnn } => format!("CALL {}", addr(nnn, labels)),
+        Instruction::JumpOffset { nnn } => format!("JP V0, {}", addr(nnn, labels)),
+        Instruction::LoadI { nnn } => format!("LD I, {}", addr(nnn, labels)),
+        Instruction::LoadILong => format!("LD I, {:#06X}", long.unwrap_or(0)),
+        _ => instr.to_string(),
+    }
+}
+
+pub fn octo(instr: &Instruction, long: Option<u16>, labels: &BTreeMap<u16, String>) -> String {
+    use Instruction::*;
+
+    match *instr {
+        Sys { nnn } => raw_bytes(nnn),
+        ScrollDown { n } => format!("scroll-down {}", n),
+        ScrollUp { n } => format!("scroll-up {}", n),
+        Cls => "clear".to_string(),
+        Ret => "return".to_string(),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        Lores => "lores".to_string(),
+        Hires => "hires".to_string(),
+        Jump { nnn } => format!("jump {}", addr(nnn, labels)),
+        Call { nnn } => format!(":call {}", addr(nnn, labels)),
+        // Octo conditionals describe when the next instruction runs,
+        // so the comparison is the inverse of the skip condition.
+        SkipEqImm { x, nn } => format!("if v{:x} != {:#04X} then", x, nn),
+        SkipNeImm { x, nn } => format!("if v{:x} == {:#04X} then", x, nn),
+        SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        LoadImm { x, nn } => format!("v{:x} := {:#04X}", x, nn),
+        AddImm { x, nn } => format!("v{:x} += {:#04X}", x, nn),
+        Move { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        LoadI { nnn } => format!("i := {}", addr(nnn, labels)),
+        JumpOffset { nnn } => format!("jump0 {}", addr(nnn, labels)),
+        Random { x, nn } => format!("v{:x} := random {:#04X}", x, nn),
+        Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipKey { x } => format!("if v{:x} -key then", x),
+        SkipNotKey { x } => format!("if v{:x} key then", x),
+        LoadILong => format!("i := long {:#06X}", long.unwrap_or(0)),
+        Plane { n } => format!("plane {}", n),
+        Audio => "audio".to_string(),
+        GetDelay { x } => format!("v{:x} := delay", x),
+        WaitKey { x } => format!("v{:x} := key", x),
+        SetDelay { x } => format!("delay := v{:x}", x),
+        SetSound { x } => format!("buzzer := v{:x}", x),
+        AddI { x } => format!("i += v{:x}", x),
+        Font { x } => format!("i := hex v{:x}", x),
+        BigFont { x } => format!("i := bighex v{:x}", x),
+        Bcd { x } => format!("bcd v{:x}", x),
+        Pitch { x } => format!("pitch := v{:x}", x),
+        Store { x } => format!("save v{:x}", x),
+        Load { x } => format!("load v{:x}", x),
+        StoreFlags { x } => format!("saveflags v{:x}", x),
+        LoadFlags { x } => format!("loadflags v{:x}", x),
+        Unknown(opcode) => raw_bytes(opcode),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classic_listing() {
+        let rom = [0x00, 0xe0, 0x61, 0x11, 0x12, 0x02];
+        let listing = disassemble(&rom, Syntax::Classic);
+
+        assert!(listing.contains("CLS"));
+        assert!(listing.contains("LD V1, 0x11"));
+        assert!(listing.contains("L202:\n"));
+        assert!(listing.contains("JP L202"));
+        assert!(listing.contains("0x0204  12 02"));
+    }
+
+    #[test]
+    fn octo_listing() {
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x61, 0x11, 0x00, 0xee];
+        let listing = disassemble(&rom, Syntax::Octo);
+
+        assert!(listing.contains(": sub_204\n"));
+        assert!(listing.contains(":call sub_204"));
+        assert!(listing.contains("v1 := 0x11"));
+        assert!(listing.contains("return"));
+    }
+
+    #[test]
+    fn long_load_spans_four_bytes() {
+        let rom = [0xf0, 0x00, 0x12, 0x34, 0x00, 0xe0];
+        let lines = decode_rom(&rom);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].long, Some(0x1234));
+        assert_eq!(lines[1].addr, 0x204);
+        assert!(disassemble(&rom, Syntax::Octo).contains("i := long 0x1234"));
+    }
+
+    #[test]
+    fn symbols_override_generated_labels() {
+        let rom = [0x22, 0x04, 0xa2, 0x06, 0x00, 0xee, 0xff, 0x00];
+        let mut symbols = SymbolMap::new();
+        symbols.insert(0x204, "draw".to_string());
+        symbols.insert(0x206, "ball".to_string());
+        symbols.insert(0x207, "unaligned".to_string());
+
+        let listing = disassemble_with_symbols(&rom, Syntax::Classic, &symbols);
+
+        assert!(listing.contains("draw:\n"));
+        assert!(listing.contains("CALL draw"));
+        assert!(listing.contains("LD I, ball"));
+        assert!(!listing.contains("unaligned"));
+    }
+
+    #[test]
+    fn jump_into_data_gets_no_label() {
+        // 0x203 is the middle of an instruction
+        let rom = [0x12, 0x03, 0x00, 0xe0, 0xab];
+        let lines = decode_rom(&rom);
+
+        assert!(auto_labels(&lines).is_empty());
+        assert!(lines[2].instr.is_none());
+    }
+}
@@ -1,19 +1,55 @@
-use sdl2::pixels::Color;
-use sdl2::video::Window;
+use std::collections::BTreeMap;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::video::{FullscreenType, Window};
 use sdl2::{render::Canvas, Sdl};
 
+use crate::filter::{self, Filter, Image};
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::screen::{Rotation, Screen};
+use crate::screenshot;
+
+/// Window title outside of games
+pub const WINDOW_TITLE: &str = "Chip-8";
 const WIDTH: u32 = 64; // Pixels
 const HEIGHT: u32 = 32; // Pixels
 
+/// Width of the debug panel to the right of the game
+pub const PANEL_WIDTH: u32 = 340;
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Size of a CHIP-8 pixel in a new window, which can then be resized
 const WIDTH_PER_PIXEL: u32 = 20;
 const HEIGHT_PER_PIXEL: u32 = 20;
 
-const PIXEL_OFF_COLOR: Color = Color::RGB(0x99, 0x66, 0x01);
-const PIXEL_ON_COLOR: Color = Color::RGB(0xff, 0xcc, 0x01);
+/// Color of the bars around the screen when the window has another aspect
+/// ratio
+const LETTERBOX_COLOR: Color = Color::RGB(0, 0, 0);
 
 pub struct Display {
     pub sdl_context: Sdl,
     pub canvas: Canvas<Window>,
+    pub palette: Palette,
+    pub phosphor: Phosphor,
+    rotation: Rotation,
+    /// Only scale the screen by whole numbers so that every pixel has the
+    /// same size
+    pub integer_scaling: bool,
+    /// Post-processing filters, in software
+    pub filters: Vec<Filter>,
+    panel_visible: bool,
+    /// Framebuffer last drawn by `render_screen`, for screenshots
+    shown: Screen,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display {
@@ -21,8 +57,13 @@ impl Display {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
         let window = video_subsystem
-            .window("Chip-8", WIDTH * WIDTH_PER_PIXEL, HEIGHT * HEIGHT_PER_PIXEL)
+            .window(
+                WINDOW_TITLE,
+                WIDTH * WIDTH_PER_PIXEL,
+                HEIGHT * HEIGHT_PER_PIXEL,
+            )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .map_err(|e| e.to_string())
@@ -34,8 +75,15 @@ impl Display {
             .unwrap();
 
         let mut screen = Display {
-            sdl_context: sdl_context,
-            canvas: canvas,
+            sdl_context,
+            canvas,
+            palette: Palette::default(),
+            phosphor: Phosphor::default(),
+            rotation: Rotation::None,
+            integer_scaling: false,
+            filters: Vec::new(),
+            panel_visible: false,
+            shown: Screen::new(),
         };
 
         screen.clear();
@@ -45,19 +93,249 @@ impl Display {
         screen
     }
 
-    pub fn to_on_color(&mut self) {
-        self.canvas.set_draw_color(PIXEL_ON_COLOR);
+    pub fn set_on_color(&mut self) {
+        let [r, g, b] = self.palette.on();
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
     }
 
-    pub fn to_off_color(&mut self) {
-        self.canvas.set_draw_color(PIXEL_OFF_COLOR);
+    pub fn set_off_color(&mut self) {
+        let [r, g, b] = self.palette.off();
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
     }
 
     pub fn clear(&mut self) {
-        self.to_off_color();
+        self.set_off_color();
         self.canvas.clear();
     }
 
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Rotates the screen and gives the window its initial size for the new
+    /// orientation
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.reset_window_size();
+    }
+
+    /// Switches between a window and borderless fullscreen
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let mode = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        let _ = window.set_fullscreen(mode);
+    }
+
+    /// Shows or hides the debug panel, widening or narrowing the window to
+    /// make room for it
+    pub fn set_panel_visible(&mut self, visible: bool) {
+        if visible == self.panel_visible {
+            return;
+        }
+        self.panel_visible = visible;
+
+        let window = self.canvas.window_mut();
+        if window.fullscreen_state() != FullscreenType::Off {
+            return;
+        }
+        let (width, height) = window.size();
+        let width = if visible {
+            width + PANEL_WIDTH
+        } else {
+            width.saturating_sub(PANEL_WIDTH).max(1)
+        };
+        let _ = window.set_size(width, height);
+    }
+
+    fn reset_window_size(&mut self) {
+        let (columns, rows) = self.rotation.size(WIDTH, HEIGHT);
+        let panel = if self.panel_visible { PANEL_WIDTH } else { 0 };
+        let window = self.canvas.window_mut();
+        if window.fullscreen_state() == FullscreenType::Off {
+            let _ = window.set_size(columns * WIDTH_PER_PIXEL + panel, rows * HEIGHT_PER_PIXEL);
+        }
+    }
+
+    fn output_size(&self) -> (u32, u32) {
+        self.canvas
+            .output_size()
+            .unwrap_or((WIDTH * WIDTH_PER_PIXEL, HEIGHT * HEIGHT_PER_PIXEL))
+    }
+
+    /// Area of the debug panel, at the right edge of the window
+    pub fn panel_rect(&self) -> Rect {
+        let (width, height) = self.output_size();
+        Rect::new(
+            width.saturating_sub(PANEL_WIDTH) as i32,
+            0,
+            PANEL_WIDTH,
+            height,
+        )
+    }
+
+    /// Part of the window left for the game
+    pub fn game_area(&self) -> Rect {
+        let (width, height) = self.output_size();
+        let panel = if self.panel_visible { PANEL_WIDTH } else { 0 };
+        Rect::new(0, 0, width.saturating_sub(panel).max(1), height)
+    }
+
+    /// Where a `width` x `height` framebuffer is drawn
+    fn screen_rect(&self, width: u32, height: u32) -> Rect {
+        let (columns, rows) = self.rotation.size(width, height);
+        viewport(self.game_area(), columns, rows, self.integer_scaling)
+    }
+
+    /// Clears the window and draws the framebuffer, rotated, scaled to the
+    /// window and shaded by the phosphor persistence
+    pub fn render_screen(&mut self, screen: &Screen) {
+        let (width, height) = (screen.width as u32, screen.height as u32);
+        let (columns, _) = self.rotation.size(width, height);
+        let area = self.screen_rect(width, height);
+
+        self.canvas.set_draw_color(LETTERBOX_COLOR);
+        self.canvas.clear();
+        let [r, g, b] = self.palette.off();
+        self.fill_rect(area, Color::RGB(r, g, b));
+
+        let shades = self.phosphor.update(screen);
+        if self.filters.is_empty() {
+            self.fill_pixels(&shades, width, height, area);
+        } else {
+            let scale = (area.width() / columns).max(1);
+            let image = self.filtered(&shades, width, height, scale);
+            self.copy_image(&image, area);
+        }
+
+        self.shown.clone_from(screen);
+    }
+
+    /// Draws every pixel as a rectangle in `area`
+    fn fill_pixels(&mut self, shades: &[u8], width: u32, height: u32, area: Rect) {
+        let (columns, rows) = self.rotation.size(width, height);
+
+        // One batch of rectangles per shade
+        let mut batches: BTreeMap<u8, Vec<Rect>> = BTreeMap::new();
+        for y in 0..height {
+            for x in 0..width {
+                let shade = shades[(y * width + x) as usize];
+                if shade != 0 {
+                    let (column, row) = self.rotation.apply(x, y, width, height);
+                    batches
+                        .entry(shade)
+                        .or_default()
+                        .push(cell(area, columns, rows, column, row));
+                }
+            }
+        }
+
+        for (shade, rects) in batches {
+            let [r, g, b] = self.palette.shade(shade);
+            self.canvas.set_draw_color(Color::RGB(r, g, b));
+            let _ = self.canvas.fill_rects(&rects);
+        }
+    }
+
+    /// The rotated, shaded framebuffer after the filters, about `scale`
+    /// times its size
+    fn filtered(&self, shades: &[u8], width: u32, height: u32, scale: u32) -> Image {
+        let (columns, rows) = self.rotation.size(width, height);
+        let mut image = Image::new(columns as usize, rows as usize, self.palette.off());
+        for y in 0..height {
+            for x in 0..width {
+                let (column, row) = self.rotation.apply(x, y, width, height);
+                let shade = shades[(y * width + x) as usize];
+                image.set(column as usize, row as usize, self.palette.shade(shade));
+            }
+        }
+
+        filter::apply(&self.filters, image, scale as usize)
+    }
+
+    /// Stretches `image` over `area`
+    fn copy_image(&mut self, image: &Image, area: Rect) {
+        let (width, height) = (image.width as u32, image.height as u32);
+        let creator = self.canvas.texture_creator();
+        let Ok(mut texture) = creator.create_texture_static(PixelFormatEnum::RGB24, width, height)
+        else {
+            return;
+        };
+        if texture
+            .update(None, &image.to_rgb(), image.width * 3)
+            .is_ok()
+        {
+            let _ = self.canvas.copy(&texture, None, area);
+        }
+    }
+
+    /// PNG of the framebuffer last drawn, at native resolution or at the
+    /// current window scale, in the current palette. Window scale ones are
+    /// rotated and filtered like the window.
+    pub fn screenshot(&self, native: bool) -> Vec<u8> {
+        let width = self.shown.width as u32;
+        let height = self.shown.height as u32;
+        if native {
+            return screenshot::to_png(&self.shown, &self.palette, 1);
+        }
+
+        let (columns, _) = self.rotation.size(width, height);
+        let scale = (self.screen_rect(width, height).width() / columns).max(1);
+        let shades: Vec<u8> = self.shown.pixels().iter().map(|on| on * 255).collect();
+        let image = self.filtered(&shades, width, height, scale);
+        screenshot::encode_png(image.width as u32, image.height as u32, &image.to_rgb())
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    pub fn present(&mut self) {
+        self.canvas.present();
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.fill_rect(rect);
+    }
+
+    /// Draws text with the built-in 3x5 font, every font pixel is `scale`
+    /// screen pixels. Lower case letters are drawn as upper case.
+    pub fn draw_text(&mut self, x: i32, y: i32, scale: u32, text: &str, color: Color) {
+        let mut rects = Vec::new();
+        let step = (GLYPH_WIDTH + 1) * scale;
+
+        for (i, c) in text.chars().enumerate() {
+            let left = x + (i as u32 * step) as i32;
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> col) != 0 {
+                        rects.push(Rect::new(
+                            left + (col * scale) as i32,
+                            y + (row as u32 * scale) as i32,
+                            scale,
+                            scale,
+                        ));
+                    }
+                }
+            }
+        }
+
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.fill_rects(&rects);
+    }
+
+    /// Width and height of `text` as drawn by `draw_text`
+    pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+        let chars = text.chars().count() as u32;
+        let width = (chars * (GLYPH_WIDTH + 1)).saturating_sub(1);
+
+        (width * scale, GLYPH_HEIGHT * scale)
+    }
+
     pub fn get_height_per_pixel() -> u32 {
         HEIGHT_PER_PIXEL
     }
@@ -66,3 +344,156 @@ impl Display {
         WIDTH_PER_PIXEL
     }
 }
+
+/// The largest `columns` x `rows` shaped rectangle that fits `area`, centered
+/// with bars on the sides that are left over. `integer` keeps the scale a
+/// whole number, leaving wider bars.
+pub fn viewport(area: Rect, columns: u32, rows: u32, integer: bool) -> Rect {
+    let (width, height) = if integer {
+        let scale = (area.width() / columns).min(area.height() / rows).max(1);
+        (columns * scale, rows * scale)
+    } else if area.width() * rows > area.height() * columns {
+        (area.height() * columns / rows, area.height())
+    } else {
+        (area.width(), area.width() * rows / columns)
+    };
+
+    Rect::new(
+        area.x() + (area.width() as i32 - width as i32) / 2,
+        area.y() + (area.height() as i32 - height as i32) / 2,
+        width.max(1),
+        height.max(1),
+    )
+}
+
+/// Rectangle of pixel (`column`, `row`) when a `columns` x `rows` screen is
+/// drawn into `area`. Pixels share their edges, so there are no gaps when the
+/// scale is not a whole number.
+fn cell(area: Rect, columns: u32, rows: u32, column: u32, row: u32) -> Rect {
+    let left = column * area.width() / columns;
+    let right = (column + 1) * area.width() / columns;
+    let top = row * area.height() / rows;
+    let bottom = (row + 1) * area.height() / rows;
+
+    Rect::new(
+        area.x() + left as i32,
+        area.y() + top as i32,
+        (right - left).max(1),
+        (bottom - top).max(1),
+    )
+}
+
+/// Rows of a 3x5 glyph, bit 2 is the leftmost column.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '$' => [0b011, 0b110, 0b010, 0b011, 0b110],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '?' => [0b111, 0b001, 0b010, 0b000, 0b010],
+        '@' => [0b010, 0b101, 0b111, 0b100, 0b011],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        '\\' => [0b100, 0b100, 0b010, 0b001, 0b001],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '^' => [0b010, 0b101, 0b000, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '`' => [0b100, 0b010, 0b000, 0b000, 0b000],
+        '{' => [0b011, 0b010, 0b100, 0b010, 0b011],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '}' => [0b110, 0b010, 0b001, 0b010, 0b110],
+        '~' => [0b000, 0b011, 0b110, 0b000, 0b000],
+        ' ' => [0; 5],
+        // Anything else is drawn as a filled box
+        _ => [0b111; 5],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_printable_ascii_char_has_a_glyph() {
+        for c in ' '..='~' {
+            assert_ne!(glyph(c), [0b111; 5], "missing glyph for {:?}", c);
+        }
+        assert_eq!(glyph('a'), glyph('A'));
+    }
+
+    #[test]
+    fn viewport_letterboxes() {
+        let window = Rect::new(0, 0, 1000, 400);
+        assert_eq!(viewport(window, 64, 32, false), Rect::new(100, 0, 800, 400));
+        assert_eq!(viewport(window, 64, 32, true), Rect::new(116, 8, 768, 384));
+
+        let tall = Rect::new(0, 0, 640, 1000);
+        assert_eq!(viewport(tall, 64, 32, false), Rect::new(0, 340, 640, 320));
+        assert_eq!(viewport(tall, 32, 64, false), Rect::new(70, 0, 500, 1000));
+    }
+
+    #[test]
+    fn cells_cover_the_viewport() {
+        let area = Rect::new(10, 0, 100, 50);
+        assert_eq!(cell(area, 64, 32, 0, 0), Rect::new(10, 0, 1, 1));
+        let last = cell(area, 64, 32, 63, 31);
+        assert_eq!((last.right(), last.bottom()), (110, 50));
+    }
+
+    #[test]
+    fn text_size() {
+        assert_eq!(Display::text_size("PC", 2), (14, 10));
+        assert_eq!(Display::text_size("", 2), (0, 10));
+    }
+}
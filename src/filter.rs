@@ -0,0 +1,525 @@
+//! Post-processing filters for the window, run in software on the framebuffer
+//! before it is shown, so they work without a GPU.
+//!
+//! Edge smoothing runs first on the emulated pixels. The image is then scaled
+//! up to the window with every emulated pixel as a square `cell` pixels wide,
+//! and the other filters run on that in the order given.
+
+/// Share of the brightness scanlines keep, out of 256
+const SCANLINE_KEEP: u32 = 150;
+/// Share of the brightness grid lines keep, out of 256
+const GRID_KEEP: u32 = 128;
+/// How much of the blurred image glow adds, out of 256
+const GLOW_STRENGTH: u32 = 160;
+
+pub const NAMES: [&str; 5] = ["scale2x", "scale3x", "scanlines", "grid", "glow"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Smooths diagonal edges by doubling every pixel (AdvMAME2x)
+    Scale2x,
+    /// Smooths diagonal edges by tripling every pixel (AdvMAME3x)
+    Scale3x,
+    /// Darkens the bottom of every pixel row like the gaps between the
+    /// scanlines of a CRT
+    Scanlines,
+    /// Darkens the edges between pixels
+    Grid,
+    /// Lets lit pixels bleed into their surroundings
+    Glow,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scale2x" => Some(Filter::Scale2x),
+            "scale3x" => Some(Filter::Scale3x),
+            "scanlines" => Some(Filter::Scanlines),
+            "grid" => Some(Filter::Grid),
+            "glow" => Some(Filter::Glow),
+            _ => None,
+        }
+    }
+
+    /// A comma separated list like `scale2x,scanlines`
+    pub fn parse_list(text: &str) -> Option<Vec<Self>> {
+        text.split(',')
+            .map(|name| Self::from_name(name.trim()))
+            .collect()
+    }
+
+    fn smooths(self) -> bool {
+        matches!(self, Filter::Scale2x | Filter::Scale3x)
+    }
+}
+
+/// An RGB image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, color: [u8; 3]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Three bytes per pixel, rows top to bottom
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.concat()
+    }
+
+    /// The pixel `dx`, `dy` away from (`x`, `y`), repeating the edges
+    fn neighbor(&self, x: usize, y: usize, dx: isize, dy: isize) -> [u8; 3] {
+        let x = x.saturating_add_signed(dx).min(self.width - 1);
+        let y = y.saturating_add_signed(dy).min(self.height - 1);
+        self.get(x, y)
+    }
+}
+
+/// Runs `filters` over `image`, an image with one pixel per emulated pixel,
+/// and scales it up by `scale`, or the nearest multiple of the smoothing
+/// filters' scale
+pub fn apply(filters: &[Filter], mut image: Image, scale: usize) -> Image {
+    let mut cell = 1;
+    for filter in filters.iter().filter(|filter| filter.smooths()) {
+        (image, cell) = match filter {
+            Filter::Scale2x => (scale2x(&image), cell * 2),
+            _ => (scale3x(&image), cell * 3),
+        };
+    }
+
+    let factor = (scale / cell).max(1);
+    image = scale_nearest(&image, factor);
+    cell *= factor;
+
+    for filter in filters {
+        match filter {
+            Filter::Scanlines => scanlines(&mut image, cell),
+            Filter::Grid => grid(&mut image, cell),
+            Filter::Glow => glow(&mut image, cell),
+            Filter::Scale2x | Filter::Scale3x => {}
+        }
+    }
+
+    image
+}
+
+pub fn scale_nearest(image: &Image, factor: usize) -> Image {
+    let mut out = Image::new(image.width * factor, image.height * factor, [0; 3]);
+    for y in 0..out.height {
+        for x in 0..out.width {
+            out.set(x, y, image.get(x / factor, y / factor));
+        }
+    }
+
+    out
+}
+
+pub fn scale2x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 2, image.height * 2, [0; 3]);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let p = image.get(x, y);
+            let a = image.neighbor(x, y, 0, -1);
+            let b = image.neighbor(x, y, 1, 0);
+            let c = image.neighbor(x, y, -1, 0);
+            let d = image.neighbor(x, y, 0, 1);
+
+            let pick = |edge: bool, color: [u8; 3]| if edge { color } else { p };
+            let (x, y) = (x * 2, y * 2);
+            out.set(x, y, pick(c == a && c != d && a != b, a));
+            out.set(x + 1, y, pick(a == b && a != c && b != d, b));
+            out.set(x, y + 1, pick(d == c && d != b && c != a, c));
+            out.set(x + 1, y + 1, pick(b == d && b != a && d != c, d));
+        }
+    }
+
+    out
+}
+
+pub fn scale3x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 3, image.height * 3, [0; 3]);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            // a b c
+            // d e f
+            // g h i
+            let n = |dx, dy| image.neighbor(x, y, dx, dy);
+            let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+            let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+            let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+
+            let top_left = d == b && b != f && d != h;
+            let top_right = b == f && b != d && f != h;
+            let bottom_left = d == h && d != b && h != f;
+            let bottom_right = h == f && d != h && b != f;
+
+            let pick = |edge: bool, color: [u8; 3]| if edge { color } else { e };
+            let block = [
+                pick(top_left, d),
+                pick((top_left && e != c) || (top_right && e != a), b),
+                pick(top_right, f),
+                pick((top_left && e != g) || (bottom_left && e != a), d),
+                e,
+                pick((top_right && e != i) || (bottom_right && e != c), f),
+                pick(bottom_left, d),
+                pick((bottom_left && e != i) || (bottom_right && e != g), h),
+                pick(bottom_right, f),
+            ];
+            for (n, color) in block.into_iter().enumerate() {
+                out.set(x * 3 + n % 3, y * 3 + n / 3, color);
+            }
+        }
+    }
+
+    out
+}
+
+/// Darkens the bottom third of every `cell` high row, or every other line
+/// for cells smaller than 3
+pub fn scanlines(image: &mut Image, cell: usize) {
+    if cell < 2 {
+        return;
+    }
+    let dark = (cell / 3).max(1);
+
+    for y in (0..image.height).filter(|y| y % cell >= cell - dark) {
+        for x in 0..image.width {
+            image.set(x, y, darken(image.get(x, y), SCANLINE_KEEP));
+        }
+    }
+}
+
+/// Darkens the last line and column of every `cell` sized square
+pub fn grid(image: &mut Image, cell: usize) {
+    if cell < 3 {
+        return;
+    }
+
+    for (y, row) in image.pixels.chunks_mut(image.width).enumerate() {
+        if y % cell == cell - 1 {
+            row.iter_mut()
+                .for_each(|pixel| *pixel = darken(*pixel, GRID_KEEP));
+        } else {
+            row.iter_mut()
+                .skip(cell - 1)
+                .step_by(cell)
+                .for_each(|pixel| *pixel = darken(*pixel, GRID_KEEP));
+        }
+    }
+}
+
+/// Lightens every pixel towards a blurred copy of the image, so light spills
+/// over about half a cell into darker surroundings. The blur runs on a copy
+/// shrunk to about four pixels per cell and is scaled back up smoothly, which
+/// looks the same for a blur this wide and keeps big windows fast.
+pub fn glow(image: &mut Image, cell: usize) {
+    let step = (cell / 4).max(1);
+    let radius = (cell / 2 / step).max(1);
+    let mut blurred = shrink(image, step);
+    // Two box blurs are close to a gaussian one
+    for _ in 0..2 {
+        blurred = blur_rows(&blurred, radius);
+        blurred = blur_columns(&blurred, radius);
+    }
+
+    let columns: Vec<_> = (0..image.width)
+        .map(|x| neighbors(x, step, blurred.width))
+        .collect();
+    let mut line = vec![[0; 3]; blurred.width];
+    for (y, row) in image.pixels.chunks_mut(image.width).enumerate() {
+        let (top, bottom, fy) = neighbors(y, step, blurred.height);
+        for (x, blur) in line.iter_mut().enumerate() {
+            *blur = lerp(blurred.get(x, top), blurred.get(x, bottom), fy);
+        }
+
+        for (pixel, &(left, right, fx)) in row.iter_mut().zip(&columns) {
+            let blur = lerp(line[left], line[right], fx);
+            for (channel, blur) in pixel.iter_mut().zip(blur) {
+                let light = u32::from(blur.saturating_sub(*channel)) * GLOW_STRENGTH / 256;
+                *channel += light as u8;
+            }
+        }
+    }
+}
+
+/// Averages every `step` x `step` block into one pixel
+fn shrink(image: &Image, step: usize) -> Image {
+    let width = image.width.div_ceil(step);
+    let height = image.height.div_ceil(step);
+    let mut sums = vec![[0u32; 3]; width * height];
+    let mut counts = vec![0u32; width * height];
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let i = y / step * width + x / step;
+            for (sum, channel) in sums[i].iter_mut().zip(image.get(x, y)) {
+                *sum += u32::from(channel);
+            }
+            counts[i] += 1;
+        }
+    }
+
+    Image {
+        width,
+        height,
+        pixels: sums
+            .iter()
+            .zip(&counts)
+            .map(|(sum, count)| sum.map(|sum| (sum / count) as u8))
+            .collect(),
+    }
+}
+
+/// The two pixels of an image shrunk by `step` around pixel `n` of the full
+/// size one, and how far `n` is from the first towards the second in 1/256
+fn neighbors(n: usize, step: usize, len: usize) -> (usize, usize, u32) {
+    // Shrunk pixels sit in the middle of their blocks. Positions are in
+    // 1/(2 * step) of a shrunk pixel to stay whole.
+    let span = 2 * step as isize;
+    let position = (2 * n + 1) as isize - step as isize;
+    let first = position.div_euclid(span);
+    let fraction = (position.rem_euclid(span) * 256 / span) as u32;
+    let clamp = |i: isize| i.clamp(0, len as isize - 1) as usize;
+
+    (clamp(first), clamp(first + 1), fraction)
+}
+
+/// `fraction` / 256 of the way from `from` to `to`
+fn lerp(from: [u8; 3], to: [u8; 3], fraction: u32) -> [u8; 3] {
+    let mut out = from;
+    for (out, (from, to)) in out.iter_mut().zip(from.iter().zip(to)) {
+        *out = ((u32::from(*from) * (256 - fraction) + u32::from(to) * fraction) / 256) as u8;
+    }
+    out
+}
+
+/// Running sum over a window of pixels, for box blurs
+struct Window {
+    sums: [u32; 3],
+    /// 2^32 / the window size, to divide by multiplying
+    reciprocal: u64,
+}
+
+impl Window {
+    fn new(radius: usize) -> Self {
+        let size = (radius * 2 + 1) as u64;
+        Self {
+            sums: [0; 3],
+            reciprocal: (1 << 32) / size + 1,
+        }
+    }
+
+    fn add(&mut self, color: [u8; 3]) {
+        for (sum, channel) in self.sums.iter_mut().zip(color) {
+            *sum += u32::from(channel);
+        }
+    }
+
+    fn remove(&mut self, color: [u8; 3]) {
+        for (sum, channel) in self.sums.iter_mut().zip(color) {
+            *sum -= u32::from(channel);
+        }
+    }
+
+    fn average(&self) -> [u8; 3] {
+        self.sums
+            .map(|sum| ((u64::from(sum) * self.reciprocal) >> 32) as u8)
+    }
+}
+
+/// Averages every pixel with the `radius` pixels left and right of it,
+/// repeating the edges
+fn blur_rows(image: &Image, radius: usize) -> Image {
+    let mut out = image.clone();
+    let last = image.width as isize - 1;
+
+    for (row, out) in image
+        .pixels
+        .chunks(image.width)
+        .zip(out.pixels.chunks_mut(image.width))
+    {
+        let at = |x: isize| row[x.clamp(0, last) as usize];
+        let mut window = Window::new(radius);
+        for x in -(radius as isize)..=radius as isize {
+            window.add(at(x));
+        }
+        for (x, pixel) in out.iter_mut().enumerate() {
+            *pixel = window.average();
+            let x = x as isize;
+            window.add(at(x + radius as isize + 1));
+            window.remove(at(x - radius as isize));
+        }
+    }
+
+    out
+}
+
+/// Averages every pixel with the `radius` pixels above and below it,
+/// repeating the edges. Works a row at a time to stay cache friendly.
+fn blur_columns(image: &Image, radius: usize) -> Image {
+    let mut out = image.clone();
+    let last = image.height as isize - 1;
+    let row = |y: isize| {
+        let start = y.clamp(0, last) as usize * image.width;
+        &image.pixels[start..start + image.width]
+    };
+
+    let mut windows: Vec<Window> = (0..image.width).map(|_| Window::new(radius)).collect();
+    for y in -(radius as isize)..=radius as isize {
+        for (window, &color) in windows.iter_mut().zip(row(y)) {
+            window.add(color);
+        }
+    }
+    for (y, out) in out.pixels.chunks_mut(image.width).enumerate() {
+        let y = y as isize;
+        let (next, previous) = (row(y + radius as isize + 1), row(y - radius as isize));
+        for (((pixel, window), &next), &previous) in
+            out.iter_mut().zip(&mut windows).zip(next).zip(previous)
+        {
+            *pixel = window.average();
+            window.add(next);
+            window.remove(previous);
+        }
+    }
+
+    out
+}
+
+/// `color` with `keep` / 256 of its brightness
+fn darken(color: [u8; 3], keep: u32) -> [u8; 3] {
+    color.map(|channel| (u32::from(channel) * keep / 256) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::snapshot;
+
+    const OFF: [u8; 3] = [0; 3];
+    const ON: [u8; 3] = [255; 3];
+
+    /// Image from rows of `#` and `.`
+    fn image(rows: &[&str]) -> Image {
+        let mut image = Image::new(rows[0].len(), rows.len(), OFF);
+        for (y, row) in snapshot::parse_ascii(&rows.join("\n")).iter().enumerate() {
+            for (x, on) in row.iter().enumerate() {
+                if *on {
+                    image.set(x, y, ON);
+                }
+            }
+        }
+        image
+    }
+
+    fn rows(image: &Image) -> Vec<String> {
+        (0..image.height)
+            .map(|y| {
+                (0..image.width)
+                    .map(|x| if image.get(x, y) == ON { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_smooths_diagonals() {
+        let diagonal = image(&["#..", "##.", "###"]);
+        assert_eq!(
+            rows(&scale2x(&diagonal)),
+            ["##....", "###...", "###...", "#####.", "######", "######"]
+        );
+
+        // Straight edges stay as they are
+        let line = image(&["##", ".."]);
+        assert_eq!(rows(&scale2x(&line)), rows(&scale_nearest(&line, 2)));
+    }
+
+    #[test]
+    fn scale3x_smooths_diagonals() {
+        let diagonal = image(&["#..", "##.", "###"]);
+        assert_eq!(
+            rows(&scale3x(&diagonal)),
+            [
+                "###......",
+                "####.....",
+                "####.....",
+                "#####....",
+                "######...",
+                "########.",
+                "#########",
+                "#########",
+                "#########",
+            ]
+        );
+    }
+
+    #[test]
+    fn scanlines_and_grid() {
+        let mut lit = Image::new(3, 6, ON);
+        scanlines(&mut lit, 3);
+        let column: Vec<u8> = (0..6).map(|y| lit.get(0, y)[0]).collect();
+        assert_eq!(column, [255, 255, 149, 255, 255, 149]);
+
+        let mut lit = Image::new(6, 3, ON);
+        grid(&mut lit, 3);
+        let row: Vec<u8> = (0..6).map(|x| lit.get(x, 0)[0]).collect();
+        assert_eq!(row, [255, 255, 127, 255, 255, 127]);
+    }
+
+    #[test]
+    fn glow_only_lightens_the_surroundings() {
+        let mut dot = scale_nearest(&image(&["...", ".#.", "..."]), 4);
+        glow(&mut dot, 4);
+
+        assert_eq!(dot.get(5, 5), ON);
+        assert!(dot.get(3, 5)[0] > 0);
+        assert_eq!(dot.get(0, 0), OFF);
+    }
+
+    #[test]
+    fn big_glows_are_smooth() {
+        let mut dot = scale_nearest(&image(&["...", ".#.", "..."]), 20);
+        glow(&mut dot, 20);
+
+        // Brightness falls off steadily away from the lit cell
+        let row: Vec<u8> = (0..20).map(|x| dot.get(x, 30)[0]).collect();
+        assert!(row.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", row);
+        assert!(row[19] > row[10] && row[10] > row[0]);
+    }
+
+    #[test]
+    fn apply_scales_to_whole_cells() {
+        let one = image(&["#"]);
+        let filters = [Filter::Scanlines, Filter::Scale2x];
+        assert_eq!(apply(&filters, one.clone(), 7).width, 6);
+        assert_eq!(apply(&[], one, 7).width, 7);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            Filter::parse_list("scale2x, glow"),
+            Some(vec![Filter::Scale2x, Filter::Glow])
+        );
+        assert_eq!(Filter::parse_list("scale2x,blur"), None);
+        for name in NAMES {
+            assert!(Filter::from_name(name).is_some());
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Headless harness shared by the fuzz targets in `fuzz/` and the regression
+//! test that replays the saved crash inputs in `fuzz/regressions`.
+//!
+//! An interpreter input is split into a profile byte, a key script and the ROM:
+//!
+//! ```text
+//! [profile] [frames n] [n little endian u16 key masks] [rom ...]
+//! ```
+//!
+//! Missing parts are treated as empty, so every byte string is a valid input.
+
+use crate::chip::{self, Chip8};
+use crate::disasm::{self, Syntax};
+use crate::instruction::Instruction;
+use crate::movie;
+use crate::quirks::{self, Quirks};
+
+pub const FRAMES: u64 = 30;
+pub const INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+pub struct Input<'a> {
+    pub quirks: Quirks,
+    /// Key pad bit masks for the first frames, bit N is key N
+    pub keys: Vec<u16>,
+    pub rom: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn parse(data: &'a [u8]) -> Self {
+        let profile = data
+            .first()
+            .map_or(0, |p| usize::from(*p) % quirks::PROFILES.len());
+        let quirks = Quirks::from_name(quirks::PROFILES[profile]).unwrap_or_default();
+
+        let frames = data.get(1).map_or(0, |n| usize::from(*n));
+        let script = data.get(2..).unwrap_or(&[]);
+        let script_len = (frames * 2).min(script.len());
+
+        Self {
+            quirks,
+            keys: script[..script_len]
+                .chunks_exact(2)
+                .map(|mask| u16::from_le_bytes([mask[0], mask[1]]))
+                .collect(),
+            rom: &script[script_len..],
+        }
+    }
+}
+
+/// Runs the input for `FRAMES` frames and panics if the machine breaks an
+/// invariant: the stack stays within its 16 entries, memory never grows and
+/// PC stays inside memory.
+pub fn run(data: &[u8]) -> Chip8 {
+    let input = Input::parse(data);
+    let mut chip = Chip8::from_rom(input.rom, input.quirks);
+    let memory_len = chip.memory().len();
+
+    for frame in 0..FRAMES {
+        let mask = input.keys.get(frame as usize).copied().unwrap_or(0);
+        chip.key_pad = movie::key_pad(mask);
+
+        chip.run_frame(INSTRUCTIONS_PER_FRAME);
+
+        assert!(
+            chip.stack.len() <= chip::STACK_SIZE,
+            "stack grew to {}",
+            chip.stack.len()
+        );
+        assert_eq!(chip.memory().len(), memory_len, "memory changed size");
+        assert!(
+            usize::from(chip.pc()) < memory_len,
+            "PC {:#x} left memory",
+            chip.pc()
+        );
+    }
+
+    chip
+}
+
+/// Disassembles `data` in both syntaxes and checks that every opcode in it
+/// encodes back to the same word.
+pub fn decode(data: &[u8]) {
+    for syntax in [Syntax::Classic, Syntax::Octo] {
+        disasm::disassemble(data, syntax);
+    }
+
+    for pair in data.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([pair[0], pair[1]]);
+        let instr = Instruction::decode(opcode);
+        assert_eq!(
+            instr.encode(),
+            opcode,
+            "{:?} does not encode to {:#06x}",
+            instr,
+            opcode
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_profile_keys_and_rom() {
+        let input = Input::parse(&[1, 2, 0x01, 0x80, 0x20, 0x00, 0x12, 0x00]);
+
+        assert_eq!(input.quirks, Quirks::superchip());
+        assert_eq!(input.keys, vec![0x8001, 0x0020]);
+        assert_eq!(input.rom, &[0x12, 0x00]);
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let data: Vec<u8> = (0..=u16::MAX).flat_map(u16::to_be_bytes).collect();
+        decode(&data);
+    }
+
+    #[test]
+    fn short_inputs_are_valid() {
+        assert!(Input::parse(&[]).rom.is_empty());
+        assert_eq!(Input::parse(&[0, 200, 1, 2, 3]).keys, vec![0x0201]);
+    }
+}
@@ -0,0 +1,244 @@
+//! Runs a ROM for a fixed number of frames without opening a window, for
+//! batch runs on machines without a display.
+
+use serde::Serialize;
+
+use crate::capture;
+use crate::chip::{Chip8, Fault, FrameBudget, RunOptions};
+use crate::movie::Movie;
+
+/// Process exit codes for each outcome. 1 is left for usage and I/O errors.
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAULT: i32 = 2;
+pub const EXIT_TIMEOUT: i32 = 3;
+
+/// CXNN seed of batch runs without `--seed`, so they repeat exactly
+pub const DEFAULT_SEED: u64 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Ran every frame
+    Finished,
+    /// Reached a jump to itself
+    Halted,
+    Fault(Fault),
+    /// Did not halt in time, only when halting is required
+    Timeout,
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Finished | Outcome::Halted => EXIT_SUCCESS,
+            Outcome::Fault(_) => EXIT_FAULT,
+            Outcome::Timeout => EXIT_TIMEOUT,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Finished => "finished",
+            Outcome::Halted => "halted",
+            Outcome::Fault(_) => "fault",
+            Outcome::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dump {
+    Ascii,
+    Pbm,
+    Png,
+}
+
+impl Dump {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ascii" => Some(Dump::Ascii),
+            "pbm" => Some(Dump::Pbm),
+            "png" => Some(Dump::Png),
+            _ => None,
+        }
+    }
+
+    pub fn render(self, chip: &Chip8) -> Vec<u8> {
+        match self {
+            Dump::Ascii => chip.screen.to_ascii().into_bytes(),
+            Dump::Pbm => chip.screen.to_pbm().into_bytes(),
+            Dump::Png => chip.screen.to_png(),
+        }
+    }
+}
+
+/// Runs `rom` for up to `frames` frames. Stops early on a fault or once the
+/// ROM halts; with `until_halt` not halting in time is a timeout.
+pub fn run(rom: &[u8], mut options: RunOptions, frames: u64, until_halt: bool) -> (Chip8, Outcome) {
+    options.seed = options.seed.or(Some(DEFAULT_SEED));
+    let (mut chip, instr_per_secs) = prepare(rom, &mut options);
+    let mut capture = capture::start(
+        options.gif.as_deref(),
+        options.raw_frames,
+        options.palette.clone(),
+    )
+    .expect("Error creating capture files!");
+
+    let mut budget = FrameBudget::new(instr_per_secs);
+    let outcome = loop {
+        if let Some(fault) = chip.fault {
+            break Outcome::Fault(fault);
+        }
+        if chip.halted() {
+            break Outcome::Halted;
+        }
+        if chip.frame >= frames {
+            break if until_halt {
+                Outcome::Timeout
+            } else {
+                Outcome::Finished
+            };
+        }
+
+        let frame = chip.frame as usize;
+        chip.key_pad = options
+            .replay
+            .as_ref()
+            .and_then(|movie| movie.key_pad(frame))
+            .unwrap_or_default();
+        if let Some(movie) = chip.recording.as_mut() {
+            movie.record(&chip.key_pad);
+        }
+
+        chip.run_frame(budget.take_frame());
+        if let Some(capture) = capture.as_mut() {
+            capture
+                .frame(&chip.screen, chip.sound_timer > 0)
+                .expect("Error writing capture!");
+        }
+    };
+
+    if let Some(capture) = capture {
+        capture.finish().expect("Error writing capture!");
+    }
+
+    (chip, outcome)
+}
+
+/// A machine running `rom` without a window, set up from `options`, and the
+/// instructions per second to run it at
+pub(crate) fn prepare(rom: &[u8], options: &mut RunOptions) -> (Chip8, f32) {
+    let mut chip = Chip8::from_rom(rom, options.quirks);
+    chip.tracer = options.tracer.take();
+    chip.profiler = options.profiler.take();
+    chip.callgraph = options.callgraph.take();
+    chip.coverage = options.coverage.take();
+    if let Some(seed) = options.seed {
+        chip.set_seed(seed);
+    }
+
+    let mut instr_per_secs = options.instr_per_secs;
+    if let Some(movie) = &options.replay {
+        movie.prepare(&mut chip);
+        instr_per_secs = movie.instr_per_secs;
+    }
+    if options.record {
+        chip.recording = Some(Movie::new(rom, chip.seed(), chip.quirks, instr_per_secs));
+    }
+
+    (chip, instr_per_secs)
+}
+
+/// Machine state at the end of a run, written as JSON
+#[derive(Serialize)]
+pub struct Summary {
+    pub outcome: &'static str,
+    pub fault: Option<String>,
+    pub frames: u64,
+    pub pc: String,
+    pub i: String,
+    pub v: Vec<u8>,
+    pub stack: Vec<String>,
+    pub delay: u16,
+    pub sound: u16,
+    pub state_hash: String,
+}
+
+impl Summary {
+    pub fn new(chip: &Chip8, outcome: Outcome) -> Self {
+        Self {
+            outcome: outcome.name(),
+            fault: match outcome {
+                Outcome::Fault(fault) => Some(fault.to_string()),
+                _ => None,
+            },
+            frames: chip.frame,
+            pc: format!("{:#05x}", chip.pc()),
+            i: format!("{:#05x}", chip.ireg()),
+            v: chip.registers().to_vec(),
+            stack: chip
+                .stack
+                .iter()
+                .map(|addr| format!("{:#05x}", addr))
+                .collect(),
+            delay: chip.delay_timer,
+            sound: chip.sound_timer,
+            state_hash: format!("{:016x}", chip.state_hash()),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Error encoding summary!")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_rom(rom: &[u8], until_halt: bool) -> (Chip8, Outcome) {
+        run(rom, RunOptions::default(), 10, until_halt)
+    }
+
+    #[test]
+    fn outcomes() {
+        // 0x200: V0 = 7, jump to 0x202
+        let (chip, outcome) = run_rom(&[0x60, 0x07, 0x12, 0x02], true);
+        assert_eq!(outcome, Outcome::Halted);
+        assert_eq!(chip.registers()[0], 7);
+
+        let (_, outcome) = run_rom(&[0x00, 0xee], false);
+        assert_eq!(outcome, Outcome::Fault(Fault::StackUnderflow { pc: 0x200 }));
+        assert_eq!(outcome.exit_code(), EXIT_FAULT);
+
+        // 0x200: V0 += 1, jump to 0x200
+        let (chip, outcome) = run_rom(&[0x70, 0x01, 0x12, 0x00], false);
+        assert_eq!(outcome, Outcome::Finished);
+        assert_eq!(chip.frame, 10);
+
+        let (_, outcome) = run_rom(&[0x70, 0x01, 0x12, 0x00], true);
+        assert_eq!(outcome.exit_code(), EXIT_TIMEOUT);
+    }
+
+    #[test]
+    fn random_numbers_repeat_without_a_seed() {
+        // 0x200: V0 = random, V1 = random, jump to 0x204
+        let rom = [0xc0, 0xff, 0xc1, 0xff, 0x12, 0x04];
+        let (first, _) = run_rom(&rom, true);
+        let (second, _) = run_rom(&rom, true);
+
+        assert_eq!(first.seed(), DEFAULT_SEED);
+        assert_eq!(first.state_hash(), second.state_hash());
+    }
+
+    #[test]
+    fn summary_json() {
+        let (chip, outcome) = run_rom(&[0xff, 0xff], false);
+        let json: serde_json::Value =
+            serde_json::from_str(&Summary::new(&chip, outcome).to_json()).unwrap();
+
+        assert_eq!(json["outcome"], "fault");
+        assert_eq!(json["fault"], "unknown instruction 0xffff at 0x200");
+        assert_eq!(json["pc"], "0x202");
+        assert_eq!(json["v"].as_array().unwrap().len(), 16);
+    }
+}
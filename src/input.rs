@@ -1,16 +1,65 @@
-use sdl2::{event::Event, keyboard::Keycode, EventPump};
+use std::path::{Path, PathBuf};
+
+use sdl2::controller::{Button, GameController};
+use sdl2::keyboard::Mod;
+use sdl2::{event::Event, keyboard::Keycode, EventPump, GameControllerSubsystem};
 
 use crate::display::Display;
+use crate::menu;
 
 #[derive(PartialEq)]
 pub enum InputAction {
+    /// The window was closed
     BreakDisplay,
+    /// Esc or the controller's back button, to leave the game
+    Back,
+    ToggleOverlay,
+    /// Scroll the overlay memory view by this many pages
+    ScrollMemory(i32),
+    /// Let the overlay memory view follow I again
+    FollowIndex,
+    /// Save a PNG of the screen, at native resolution or at the window scale
+    Screenshot {
+        native: bool,
+    },
+    /// Start or stop recording a GIF and WAV
+    ToggleCapture,
+    /// Switch to the next palette
+    CyclePalette,
+    /// Switch between a window and borderless fullscreen
+    ToggleFullscreen,
+    /// A ROM was dropped on the window
+    LoadRom(PathBuf),
+    TogglePause,
+    /// Step through the speeds, faster for positive steps
+    ChangeSpeed(i32),
+    None,
+}
+
+/// Navigation in the ROM menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Move the selection by this many rows
+    Move(isize),
+    First,
+    Last,
+    Select,
+    Back,
+    /// The window was closed
+    Quit,
+    ToggleFullscreen,
     None,
 }
 
+/// Rows PageUp and PageDown move in the menu
+const MENU_PAGE: isize = 10;
+
 pub struct Input {
     pub key_pad: [bool; 16],
     event_pump: EventPump,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    /// Open game controllers, which only send events while open
+    controllers: Vec<GameController>,
 }
 
 impl Input {
@@ -18,19 +67,143 @@ impl Input {
         let event_pump = display.sdl_context.event_pump().unwrap();
 
         Self {
-            event_pump: event_pump,
+            event_pump,
             key_pad: [false; 16],
+            controller_subsystem: display.sdl_context.game_controller().ok(),
+            controllers: Vec::new(),
+        }
+    }
+
+    /// Opens a newly connected controller. SDL also reports the ones
+    /// connected at startup this way.
+    fn open_controller(&mut self, index: u32) {
+        let Some(subsystem) = &self.controller_subsystem else {
+            return;
+        };
+        match subsystem.open(index) {
+            Ok(controller) => self.controllers.push(controller),
+            Err(err) => eprintln!("Error opening controller {}: {}", index, err),
         }
     }
 
+    pub fn menu_input(&mut self) -> MenuAction {
+        while let Some(event) = self.event_pump.poll_event() {
+            let action = match event {
+                Event::Quit { .. } => MenuAction::Quit,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.open_controller(which);
+                    MenuAction::None
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => match keycode {
+                    Keycode::Return if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                        MenuAction::ToggleFullscreen
+                    }
+                    Keycode::Up => MenuAction::Move(-1),
+                    Keycode::Down => MenuAction::Move(1),
+                    Keycode::PageUp => MenuAction::Move(-MENU_PAGE),
+                    Keycode::PageDown => MenuAction::Move(MENU_PAGE),
+                    Keycode::Home => MenuAction::First,
+                    Keycode::End => MenuAction::Last,
+                    Keycode::Return | Keycode::KpEnter | Keycode::Space => MenuAction::Select,
+                    Keycode::Escape => MenuAction::Back,
+                    _ => MenuAction::None,
+                },
+                Event::ControllerButtonDown { button, .. } => match button {
+                    Button::DPadUp => MenuAction::Move(-1),
+                    Button::DPadDown => MenuAction::Move(1),
+                    Button::LeftShoulder => MenuAction::Move(-MENU_PAGE),
+                    Button::RightShoulder => MenuAction::Move(MENU_PAGE),
+                    Button::A | Button::Start => MenuAction::Select,
+                    Button::B | Button::Back => MenuAction::Back,
+                    _ => MenuAction::None,
+                },
+                _ => MenuAction::None,
+            };
+
+            if action != MenuAction::None {
+                return action;
+            }
+        }
+
+        MenuAction::None
+    }
+
     pub fn handle_input(&mut self) -> InputAction {
-        for event in self.event_pump.poll_iter() {
+        while let Some(event) = self.event_pump.poll_event() {
             match event {
                 Event::Quit { .. } => return InputAction::BreakDisplay,
                 Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => return InputAction::BreakDisplay,
+                } => return InputAction::Back,
+                Event::ControllerButtonDown {
+                    button: Button::Back,
+                    ..
+                } => return InputAction::Back,
+                Event::ControllerDeviceAdded { which, .. } => self.open_controller(which),
+                Event::DropFile { filename, .. } => {
+                    let path = PathBuf::from(filename);
+                    if is_rom(&path) {
+                        return InputAction::LoadRom(path);
+                    }
+                    eprintln!("Not a ROM: {}", path.display());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => return InputAction::ToggleOverlay,
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => return InputAction::ScrollMemory(-1),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => return InputAction::ScrollMemory(1),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Home),
+                    ..
+                } => return InputAction::FollowIndex,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    return InputAction::ToggleFullscreen
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => return InputAction::TogglePause,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus | Keycode::KpMinus),
+                    ..
+                } => return InputAction::ChangeSpeed(-1),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals | Keycode::Plus | Keycode::KpPlus),
+                    ..
+                } => return InputAction::ChangeSpeed(1),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => return InputAction::CyclePalette,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => return InputAction::ToggleCapture,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    keymod,
+                    ..
+                } => {
+                    return InputAction::Screenshot {
+                        native: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+                    }
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::Num0),
                     ..
@@ -40,7 +213,6 @@ impl Input {
                     ..
                 } => self.key_pad[0] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num1),
                     ..
@@ -50,7 +222,6 @@ impl Input {
                     ..
                 } => self.key_pad[1] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num2),
                     ..
@@ -60,7 +231,6 @@ impl Input {
                     ..
                 } => self.key_pad[2] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num3),
                     ..
@@ -70,7 +240,6 @@ impl Input {
                     ..
                 } => self.key_pad[3] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num4),
                     ..
@@ -80,7 +249,6 @@ impl Input {
                     ..
                 } => self.key_pad[4] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num5),
                     ..
@@ -90,7 +258,6 @@ impl Input {
                     ..
                 } => self.key_pad[5] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num6),
                     ..
@@ -100,7 +267,6 @@ impl Input {
                     ..
                 } => self.key_pad[6] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num7),
                     ..
@@ -110,7 +276,6 @@ impl Input {
                     ..
                 } => self.key_pad[7] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num8),
                     ..
@@ -120,7 +285,6 @@ impl Input {
                     ..
                 } => self.key_pad[8] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::Num9),
                     ..
@@ -130,7 +294,6 @@ impl Input {
                     ..
                 } => self.key_pad[9] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::A),
                     ..
@@ -140,7 +303,6 @@ impl Input {
                     ..
                 } => self.key_pad[10] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::B),
                     ..
@@ -150,7 +312,6 @@ impl Input {
                     ..
                 } => self.key_pad[11] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::C),
                     ..
@@ -158,8 +319,7 @@ impl Input {
                 Event::KeyUp {
                     keycode: Some(Keycode::C),
                     ..
-                } => self.key_pad[12] = true,
-
+                } => self.key_pad[12] = false,
 
                 Event::KeyDown {
                     keycode: Some(Keycode::D),
@@ -170,7 +330,6 @@ impl Input {
                     ..
                 } => self.key_pad[13] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::E),
                     ..
@@ -180,7 +339,6 @@ impl Input {
                     ..
                 } => self.key_pad[14] = false,
 
-
                 Event::KeyDown {
                     keycode: Some(Keycode::F),
                     ..
@@ -196,3 +354,11 @@ impl Input {
         InputAction::None
     }
 }
+
+/// Files with a ROM or Octo source extension can be dropped on the window
+fn is_rom(path: &Path) -> bool {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let extension = extension.to_ascii_lowercase();
+
+    extension == "8o" || menu::EXTENSIONS.contains(&extension.as_str())
+}
@@ -0,0 +1,343 @@
+use std::fmt;
+
+const NNN: u16 = 0x0fff;
+const NN: u16 = 0x00ff;
+const N: u16 = 0x000f;
+const X: u16 = 0x0f00;
+const Y: u16 = 0x00f0;
+
+/// A single decoded CHIP-8 instruction, including the SCHIP and XO-CHIP
+/// extensions. Register operands are register indices (0x0-0xf).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys { nnn: u16 },     // 0NNN - call machine code routine (ignored by interpreters)
+    ScrollDown { n: u8 }, // 00CN - scroll display N pixels down (SCHIP)
+    ScrollUp { n: u8 },   // 00DN - scroll display N pixels up (XO-CHIP)
+    Cls,                  // 00E0
+    Ret,                  // 00EE
+    ScrollRight,          // 00FB - scroll display 4 pixels right (SCHIP)
+    ScrollLeft,           // 00FC - scroll display 4 pixels left (SCHIP)
+    Exit,                 // 00FD - exit interpreter (SCHIP)
+    Lores,                // 00FE - 64x32 display mode (SCHIP)
+    Hires,                // 00FF - 128x64 display mode (SCHIP)
+    Jump { nnn: u16 },    // 1NNN
+    Call { nnn: u16 },    // 2NNN
+    SkipEqImm { x: u8, nn: u8 }, // 3XNN - skip if VX == NN
+    SkipNeImm { x: u8, nn: u8 }, // 4XNN - skip if VX != NN
+    SkipEqReg { x: u8, y: u8 }, // 5XY0 - skip if VX == VY
+    SaveRange { x: u8, y: u8 }, // 5XY2 - store VX..VY at I (XO-CHIP)
+    LoadRange { x: u8, y: u8 }, // 5XY3 - load VX..VY from I (XO-CHIP)
+    LoadImm { x: u8, nn: u8 }, // 6XNN
+    AddImm { x: u8, nn: u8 }, // 7XNN
+    Move { x: u8, y: u8 }, // 8XY0
+    Or { x: u8, y: u8 },  // 8XY1
+    And { x: u8, y: u8 }, // 8XY2
+    Xor { x: u8, y: u8 }, // 8XY3
+    AddReg { x: u8, y: u8 }, // 8XY4
+    Sub { x: u8, y: u8 }, // 8XY5 - VX = VX - VY
+    ShiftRight { x: u8, y: u8 }, // 8XY6
+    SubN { x: u8, y: u8 }, // 8XY7 - VX = VY - VX
+    ShiftLeft { x: u8, y: u8 }, // 8XYE
+    SkipNeReg { x: u8, y: u8 }, // 9XY0 - skip if VX != VY
+    LoadI { nnn: u16 },   // ANNN
+    JumpOffset { nnn: u16 }, // BNNN - jump to NNN + V0
+    Random { x: u8, nn: u8 }, // CXNN
+    Draw { x: u8, y: u8, n: u8 }, // DXYN
+    SkipKey { x: u8 },    // EX9E - skip if key VX is down
+    SkipNotKey { x: u8 }, // EXA1 - skip if key VX is up
+    LoadILong,            // F000 NNNN - load the following 16 bit word into I (XO-CHIP)
+    Plane { n: u8 },      // FN01 - select drawing planes (XO-CHIP)
+    Audio,                // F002 - load audio pattern from I (XO-CHIP)
+    GetDelay { x: u8 },   // FX07
+    WaitKey { x: u8 },    // FX0A
+    SetDelay { x: u8 },   // FX15
+    SetSound { x: u8 },   // FX18
+    AddI { x: u8 },       // FX1E
+    Font { x: u8 },       // FX29 - point I at small font glyph for VX
+    BigFont { x: u8 },    // FX30 - point I at big font glyph for VX (SCHIP)
+    Bcd { x: u8 },        // FX33
+    Pitch { x: u8 },      // FX3A - set audio pitch (XO-CHIP)
+    Store { x: u8 },      // FX55
+    Load { x: u8 },       // FX65
+    StoreFlags { x: u8 }, // FX75 - store V0..VX in flag registers (SCHIP)
+    LoadFlags { x: u8 },  // FX85 - load V0..VX from flag registers (SCHIP)
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(instr: u16) -> Instruction {
+        let nnn = instr & NNN;
+        let nn = (instr & NN) as u8;
+        let n = (instr & N) as u8;
+        let x = ((instr & X) >> 8) as u8;
+        let y = ((instr & Y) >> 4) as u8;
+
+        match (instr & 0xf000) >> 12 {
+            0 => match nnn {
+                0x0c0..=0x0cf => Instruction::ScrollDown { n },
+                0x0d0..=0x0df => Instruction::ScrollUp { n },
+                0x0e0 => Instruction::Cls,
+                0x0ee => Instruction::Ret,
+                0x0fb => Instruction::ScrollRight,
+                0x0fc => Instruction::ScrollLeft,
+                0x0fd => Instruction::Exit,
+                0x0fe => Instruction::Lores,
+                0x0ff => Instruction::Hires,
+                _ => Instruction::Sys { nnn },
+            },
+            1 => Instruction::Jump { nnn },
+            2 => Instruction::Call { nnn },
+            3 => Instruction::SkipEqImm { x, nn },
+            4 => Instruction::SkipNeImm { x, nn },
+            5 => match n {
+                0x0 => Instruction::SkipEqReg { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => Instruction::Unknown(instr),
+            },
+            6 => Instruction::LoadImm { x, nn },
+            7 => Instruction::AddImm { x, nn },
+            8 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddReg { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xe => Instruction::ShiftLeft { x, y },
+                _ => Instruction::Unknown(instr),
+            },
+            9 if n == 0 => Instruction::SkipNeReg { x, y },
+            0xa => Instruction::LoadI { nnn },
+            0xb => Instruction::JumpOffset { nnn },
+            0xc => Instruction::Random { x, nn },
+            0xd => Instruction::Draw { x, y, n },
+            0xe => match nn {
+                0x9e => Instruction::SkipKey { x },
+                0xa1 => Instruction::SkipNotKey { x },
+                _ => Instruction::Unknown(instr),
+            },
+            0xf => match nn {
+                0x00 if x == 0 => Instruction::LoadILong,
+                0x01 => Instruction::Plane { n: x },
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::GetDelay { x },
+                0x0a => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1e => Instruction::AddI { x },
+                0x29 => Instruction::Font { x },
+                0x30 => Instruction::BigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3a => Instruction::Pitch { x },
+                0x55 => Instruction::Store { x },
+                0x65 => Instruction::Load { x },
+                0x75 => Instruction::StoreFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => Instruction::Unknown(instr),
+            },
+            _ => Instruction::Unknown(instr),
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | u16::from(x) << 8 | u16::from(y) << 4 | n;
+        let xnn = |op: u16, x: u8, nn: u8| op | u16::from(x) << 8 | u16::from(nn);
+        let x_op = |op: u16, x: u8| op | u16::from(x) << 8;
+
+        match *self {
+            Sys { nnn } => nnn,
+            ScrollDown { n } => 0x00c0 | u16::from(n),
+            ScrollUp { n } => 0x00d0 | u16::from(n),
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            ScrollRight => 0x00fb,
+            ScrollLeft => 0x00fc,
+            Exit => 0x00fd,
+            Lores => 0x00fe,
+            Hires => 0x00ff,
+            Jump { nnn } => 0x1000 | nnn,
+            Call { nnn } => 0x2000 | nnn,
+            SkipEqImm { x, nn } => xnn(0x3000, x, nn),
+            SkipNeImm { x, nn } => xnn(0x4000, x, nn),
+            SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            LoadImm { x, nn } => xnn(0x6000, x, nn),
+            AddImm { x, nn } => xnn(0x7000, x, nn),
+            Move { x, y } => xy(0x8000, x, y, 0x0),
+            Or { x, y } => xy(0x8000, x, y, 0x1),
+            And { x, y } => xy(0x8000, x, y, 0x2),
+            Xor { x, y } => xy(0x8000, x, y, 0x3),
+            AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Sub { x, y } => xy(0x8000, x, y, 0x5),
+            ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            SubN { x, y } => xy(0x8000, x, y, 0x7),
+            ShiftLeft { x, y } => xy(0x8000, x, y, 0xe),
+            SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            LoadI { nnn } => 0xa000 | nnn,
+            JumpOffset { nnn } => 0xb000 | nnn,
+            Random { x, nn } => xnn(0xc000, x, nn),
+            Draw { x, y, n } => xy(0xd000, x, y, u16::from(n)),
+            SkipKey { x } => x_op(0xe09e, x),
+            SkipNotKey { x } => x_op(0xe0a1, x),
+            LoadILong => 0xf000,
+            Plane { n } => x_op(0xf001, n),
+            Audio => 0xf002,
+            GetDelay { x } => x_op(0xf007, x),
+            WaitKey { x } => x_op(0xf00a, x),
+            SetDelay { x } => x_op(0xf015, x),
+            SetSound { x } => x_op(0xf018, x),
+            AddI { x } => x_op(0xf01e, x),
+            Font { x } => x_op(0xf029, x),
+            BigFont { x } => x_op(0xf030, x),
+            Bcd { x } => x_op(0xf033, x),
+            Pitch { x } => x_op(0xf03a, x),
+            Store { x } => x_op(0xf055, x),
+            Load { x } => x_op(0xf065, x),
+            StoreFlags { x } => x_op(0xf075, x),
+            LoadFlags { x } => x_op(0xf085, x),
+            Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Size of the instruction in bytes. Only `F000 NNNN` spans two words.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Classic (Cowgod style) mnemonics. Addresses are printed as numbers; the
+/// operand of `F000 NNNN` is not part of the opcode and is shown as `LONG`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys { nnn } => write!(f, "SYS {:#05X}", nnn),
+            ScrollDown { n } => write!(f, "SCD {}", n),
+            ScrollUp { n } => write!(f, "SCU {}", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            SkipEqImm { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            SkipNeImm { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange { x, y } => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LoadImm { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            AddImm { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Move { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            JumpOffset { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Random { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadILong => write!(f, "LD I, LONG"),
+            Plane { n } => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Font { x } => write!(f, "LD F, V{:X}", x),
+            BigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Pitch { x } => write!(f, "LD PITCH, V{:X}", x),
+            Store { x } => write!(f, "LD [I], V{:X}", x),
+            Load { x } => write!(f, "LD V{:X}, [I]", x),
+            StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(
+            Instruction::decode(0xd12f),
+            Instruction::Draw { x: 1, y: 2, n: 0xf }
+        );
+        assert_eq!(
+            Instruction::decode(0x7a42),
+            Instruction::AddImm { x: 0xa, nn: 0x42 }
+        );
+        assert_eq!(
+            Instruction::decode(0x2345),
+            Instruction::Call { nnn: 0x345 }
+        );
+    }
+
+    #[test]
+    fn decodes_extensions() {
+        assert_eq!(
+            Instruction::decode(0x00c4),
+            Instruction::ScrollDown { n: 4 }
+        );
+        assert_eq!(Instruction::decode(0x00ff), Instruction::Hires);
+        assert_eq!(Instruction::decode(0xf000), Instruction::LoadILong);
+        assert_eq!(Instruction::decode(0xf201), Instruction::Plane { n: 2 });
+        assert_eq!(
+            Instruction::decode(0x5123),
+            Instruction::LoadRange { x: 1, y: 2 }
+        );
+        assert_eq!(Instruction::decode(0xf000).size(), 4);
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        assert_eq!(Instruction::decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(Instruction::decode(0x800f), Instruction::Unknown(0x800f));
+        assert_eq!(Instruction::decode(0x9001), Instruction::Unknown(0x9001));
+        assert_eq!(Instruction::decode(0xf100), Instruction::Unknown(0xf100));
+        assert_eq!(Instruction::decode(0xe000), Instruction::Unknown(0xe000));
+    }
+
+    #[test]
+    fn round_trips_every_opcode() {
+        for opcode in 0..=u16::MAX {
+            let instr = Instruction::decode(opcode);
+
+            assert_eq!(instr.encode(), opcode, "{:?}", instr);
+            assert_eq!(Instruction::decode(instr.encode()), instr);
+        }
+    }
+
+    #[test]
+    fn displays_classic_mnemonics() {
+        assert_eq!(Instruction::decode(0x6111).to_string(), "LD V1, 0x11");
+        assert_eq!(Instruction::decode(0xd12f).to_string(), "DRW V1, V2, 15");
+        assert_eq!(Instruction::decode(0x22a4).to_string(), "CALL 0x2A4");
+        assert_eq!(Instruction::decode(0xfa65).to_string(), "LD VA, [I]");
+        assert_eq!(Instruction::decode(0x5121).to_string(), "DW 0x5121");
+    }
+}
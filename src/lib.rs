@@ -0,0 +1,37 @@
+pub mod asm;
+pub mod callgraph;
+pub mod capture;
+pub mod chip;
+pub mod config;
+pub mod coverage;
+pub mod disasm;
+#[cfg(feature = "sdl")]
+pub mod display;
+pub mod filter;
+pub mod fuzz;
+pub mod headless;
+#[cfg(feature = "sdl")]
+pub mod input;
+pub mod instruction;
+#[cfg(feature = "sdl")]
+pub mod menu;
+pub mod movie;
+#[cfg(feature = "sdl")]
+pub mod osd;
+#[cfg(feature = "sdl")]
+pub mod overlay;
+pub mod palette;
+pub mod phosphor;
+pub mod profile;
+pub mod quirks;
+pub mod screen;
+pub mod screenshot;
+pub mod script;
+pub mod snapshot;
+pub mod symbols;
+pub mod terminal;
+pub mod trace;
+pub mod vectors;
+pub mod watch;
+#[cfg(feature = "sdl")]
+pub mod window;
@@ -1,9 +1,533 @@
-mod chip;
-mod display;
-mod input;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use cpu_emulator::asm;
+use cpu_emulator::callgraph::{CallGraph, Names};
+use cpu_emulator::chip::{Chip8, RunOptions};
+use cpu_emulator::config::Config;
+use cpu_emulator::coverage::Coverage;
+use cpu_emulator::disasm::{self, Syntax};
+use cpu_emulator::filter::{self, Filter};
+use cpu_emulator::headless::{self, Dump, Summary};
+#[cfg(feature = "sdl")]
+use cpu_emulator::menu::{Menu, Recent};
+use cpu_emulator::movie::Movie;
+use cpu_emulator::palette;
+use cpu_emulator::phosphor::Persistence;
+use cpu_emulator::profile::Profiler;
+use cpu_emulator::quirks::{self, Quirks};
+use cpu_emulator::screen::Rotation;
+use cpu_emulator::script::{self, Runner};
+use cpu_emulator::symbols::{self, SymbolMap};
+use cpu_emulator::terminal::{self, Glyphs};
+use cpu_emulator::trace::{self, TraceFilter, Tracer};
+use cpu_emulator::watch;
+#[cfg(feature = "sdl")]
+use cpu_emulator::window::{self, Exit, Externals};
 
 const INSTR_PER_SECS: f32 = 100.0;
 
+fn print_usage() {
+    eprintln!("Usage:");
+    eprintln!("    cpu-emulator                  pick a ROM from the menu");
+    eprintln!("    cpu-emulator run <rom.ch8> [options]");
+    eprintln!("    cpu-emulator disasm [--octo] [--symbols <file.sym>] <rom.ch8>");
+    eprintln!("    cpu-emulator asm <source.8o> [-o <rom.ch8>] [--symbols <file.sym>]");
+    eprintln!(
+        "    cpu-emulator script <rom.ch8> <script> [--quirks <profile>] [--speed <n>] [--seed <n>] [--trace <file>] [--out <dir>]"
+    );
+    eprintln!();
+    eprintln!("Run options:");
+    eprintln!(
+        "    --quirks <profile>       {} (default chip8)",
+        quirks::PROFILES.join(", ")
+    );
+    eprintln!("    --trace <file>           write an execution trace");
+    eprintln!("    --trace-pc <from-to>     only trace instructions in this address range");
+    eprintln!("    --trace-ops <8,d,...>    only trace these opcode classes");
+    eprintln!("    --trace-frames <from-to> only trace this frame window");
+    eprintln!("    --profile                print a hot-spot report on exit");
+    eprintln!("    --profile-csv <file>     write the hot-spot counts as CSV on exit");
+    eprintln!("    --callgraph <file>       write subroutine profile as folded stacks on exit");
+    eprintln!("    --callgraph-report       print inclusive/exclusive counts per subroutine");
+    eprintln!("    --symbols <file.sym>     subroutine names for the call graph");
+    eprintln!("    --coverage <file>        write an annotated coverage listing on exit");
+    eprintln!("    --seed <n>               seed for CXNN random numbers");
+    eprintln!("    --record <file>          record the key presses into a movie file");
+    eprintln!("    --replay <file>          replay a movie file, then hand over to the keyboard");
+    eprintln!(
+        "    --screenshots <dir>      where F12 saves screenshots and F9 captures (default .)"
+    );
+    eprintln!("    --gif <file.gif>         capture every frame as GIF and the sound as WAV");
+    eprintln!("    --raw-frames             stream every frame as raw 64x32 RGB24 to stdout");
+    eprintln!(
+        "    --palette <name>         {} or a palette from the config file",
+        palette::THEMES.join(", ")
+    );
+    eprintln!("    --config <file>          settings file (default ~/.config/chip8/config.toml)");
+    eprintln!("    --rotate <degrees>       turn the screen clockwise by 90, 180 or 270 degrees");
+    eprintln!("    --integer-scale          only scale the screen by whole numbers");
+    eprintln!("    --fullscreen             start in borderless fullscreen, Alt+Enter toggles");
+    eprintln!(
+        "    --filter <a,b,...>       post-processing: {}",
+        filter::NAMES.join(", ")
+    );
+    eprintln!("    --phosphor <mode>        pixel persistence: off, blend or a decay like 0.6");
+    eprintln!("    --dev                    reload the ROM or its .8o source when it changes");
+    eprintln!("    --terminal               draw in the terminal instead of a window");
+    eprintln!("    --glyphs <kind>          terminal characters: half, quarter or braille");
+    eprintln!("    --headless               run without a window, needs --frames");
+    eprintln!("    --frames <n>             frames to run headless");
+    eprintln!("    --until-halt             exit with a timeout unless the ROM halts in time");
+    eprintln!("    --dump <format>          final screen as ascii, pbm or png (default ascii)");
+    eprintln!("    --out <file>             write the final screen here instead of stdout");
+    eprintln!("    --summary <file>         write the JSON state summary here instead of stdout");
+    eprintln!();
+    eprintln!(
+        "Headless runs exit with {} on success, {} on an emulator fault and {} on a timeout.",
+        headless::EXIT_SUCCESS,
+        headless::EXIT_FAULT,
+        headless::EXIT_TIMEOUT
+    );
+}
+
+fn exit_with_usage() -> ! {
+    print_usage();
+    process::exit(1);
+}
+
+fn disasm_command(args: &[String]) {
+    let mut syntax = Syntax::Classic;
+    let mut rom_name = None;
+    let mut symbols = SymbolMap::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            "--classic" => syntax = Syntax::Classic,
+            "--symbols" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                symbols = symbols::read(path).expect("Error reading symbol file!");
+            }
+            _ => rom_name = Some(arg),
+        }
+    }
+
+    let rom_name = rom_name.unwrap_or_else(|| exit_with_usage());
+    let rom = fs::read(rom_name).expect("Error reading rom file!");
+    print!(
+        "{}",
+        disasm::disassemble_with_symbols(&rom, syntax, &symbols)
+    );
+}
+
+fn asm_command(args: &[String]) {
+    let mut source_name = None;
+    let mut out_name = None;
+    let mut symbols_name = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_name = args.next().cloned(),
+            "--symbols" => symbols_name = args.next().cloned(),
+            _ => source_name = Some(arg.clone()),
+        }
+    }
+
+    let source_name = source_name.unwrap_or_else(|| exit_with_usage());
+    let out_name = out_name.unwrap_or_else(|| {
+        Path::new(&source_name)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let source = fs::read_to_string(&source_name).expect("Error reading source file!");
+    let program = match asm::assemble(&source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}:{}", source_name, err);
+            process::exit(1);
+        }
+    };
+
+    fs::write(&out_name, &program.rom).expect("Error writing rom file!");
+    if let Some(symbols_name) = symbols_name {
+        fs::write(symbols_name, program.symbol_map()).expect("Error writing symbol file!");
+    }
+}
+
+fn run_command(args: &[String]) {
+    let mut options = RunOptions {
+        instr_per_secs: INSTR_PER_SECS,
+        ..Default::default()
+    };
+    let mut rom_name = None;
+    let mut trace_file = None;
+    let mut profile_report = false;
+    let mut profile_csv = None;
+    let mut callgraph_file = None;
+    let mut callgraph_report = false;
+    let mut coverage_file = None;
+    let mut movie_file = None;
+    let mut palette_name = None;
+    let mut config_file = None;
+    let mut rotation = None;
+    let mut headless = false;
+    let mut in_terminal = false;
+    let mut glyphs = Glyphs::HalfBlocks;
+    let mut frames = None;
+    let mut until_halt = false;
+    let mut dump = Dump::Ascii;
+    let mut out_file = None;
+    let mut summary_file = None;
+    let mut symbols = SymbolMap::new();
+    let mut filter = TraceFilter::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace_file = Some(next_arg(&mut args)),
+            "--trace-pc" => {
+                let range = trace::parse_address_range(next_arg(&mut args));
+                filter.pc = Some(range.unwrap_or_else(|err| {
+                    eprintln!("--trace-pc: {}", err);
+                    process::exit(1);
+                }));
+            }
+            "--trace-ops" => {
+                filter.classes = Some(parse_or_exit(trace::parse_classes(next_arg(&mut args))))
+            }
+            "--trace-frames" => {
+                filter.frames = Some(parse_or_exit(trace::parse_range(next_arg(&mut args))))
+            }
+            "--quirks" => options.quirks = parse_or_exit(Quirks::from_name(next_arg(&mut args))),
+            "--profile" => profile_report = true,
+            "--profile-csv" => profile_csv = Some(next_arg(&mut args)),
+            "--callgraph" => callgraph_file = Some(next_arg(&mut args)),
+            "--callgraph-report" => callgraph_report = true,
+            "--coverage" => coverage_file = Some(next_arg(&mut args)),
+            "--seed" => options.seed = Some(parse_or_exit(next_arg(&mut args).parse().ok())),
+            "--record" => {
+                options.record = true;
+                movie_file = Some(next_arg(&mut args));
+            }
+            "--replay" => {
+                let text =
+                    fs::read_to_string(next_arg(&mut args)).expect("Error reading movie file!");
+                options.replay = Some(Movie::parse(&text).unwrap_or_else(|err| {
+                    eprintln!("Invalid movie file: {}", err);
+                    process::exit(1);
+                }));
+            }
+            "--symbols" => {
+                symbols = symbols::read(next_arg(&mut args)).expect("Error reading symbol file!")
+            }
+            "--screenshots" => options.screenshot_dir = PathBuf::from(next_arg(&mut args)),
+            "--palette" => palette_name = Some(next_arg(&mut args)),
+            "--rotate" => {
+                let degrees = next_arg(&mut args).parse().ok();
+                rotation = Some(parse_or_exit(degrees.and_then(Rotation::from_degrees)))
+            }
+            "--filter" => options.filters = parse_or_exit(Filter::parse_list(next_arg(&mut args))),
+            "--integer-scale" => options.integer_scaling = true,
+            "--fullscreen" => options.fullscreen = true,
+            "--dev" => options.watch = true,
+            "--config" => config_file = Some(PathBuf::from(next_arg(&mut args))),
+            "--phosphor" => {
+                options.persistence = parse_or_exit(Persistence::from_name(next_arg(&mut args)))
+            }
+            "--gif" => options.gif = Some(PathBuf::from(next_arg(&mut args))),
+            "--raw-frames" => options.raw_frames = true,
+            "--headless" => headless = true,
+            "--terminal" => in_terminal = true,
+            "--glyphs" => glyphs = parse_or_exit(Glyphs::from_name(next_arg(&mut args))),
+            "--frames" => frames = Some(parse_or_exit(next_arg(&mut args).parse().ok())),
+            "--until-halt" => until_halt = true,
+            "--dump" => dump = parse_or_exit(Dump::from_name(next_arg(&mut args))),
+            "--out" => out_file = Some(next_arg(&mut args)),
+            "--summary" => summary_file = Some(next_arg(&mut args)),
+            _ => rom_name = Some(arg.as_str()),
+        }
+    }
+
+    if profile_report || profile_csv.is_some() {
+        options.profiler = Some(Profiler::new());
+    }
+
+    if callgraph_report || callgraph_file.is_some() {
+        options.callgraph = Some(CallGraph::new());
+    }
+
+    if coverage_file.is_some() {
+        options.coverage = Some(Coverage::new());
+    }
+
+    if let Some(trace_file) = trace_file {
+        options.tracer =
+            Some(Tracer::to_file(trace_file, filter).expect("Error creating trace file!"));
+    }
+
+    let rom_name = rom_name.unwrap_or_else(|| exit_with_usage());
+    let rom = watch::read_rom(Path::new(rom_name)).unwrap_or_else(|err| {
+        eprintln!("{}: {}", rom_name, err);
+        process::exit(1);
+    });
+    let config = load_config(config_file);
+    options.palette = config
+        .palette_for(rom_name, palette_name)
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+    options.palettes = config.palettes().unwrap_or_default();
+    options.rotation = config.rotation_for(rom_name, rotation);
+
+    if let Some(movie) = &options.replay {
+        if !movie.matches_rom(&rom) {
+            eprintln!("The movie was recorded with a different ROM");
+            process::exit(1);
+        }
+    }
+
+    let mut outcome = None;
+    let mut cpu = if headless {
+        let frames = frames.unwrap_or_else(|| exit_with_usage());
+        if dump == Dump::Png && out_file.is_none() {
+            exit_with_usage();
+        }
+        // Raw frames own stdout, the screen and the summary have to go elsewhere
+        if options.raw_frames && (out_file.is_none() || summary_file.is_none()) {
+            exit_with_usage();
+        }
+
+        let (cpu, result) = headless::run(&rom, options, frames, until_halt);
+        outcome = Some(result);
+        cpu
+    } else if in_terminal {
+        // Raw frames would go to the terminal that is being drawn on
+        if options.raw_frames {
+            exit_with_usage();
+        }
+
+        let cpu = terminal::run(&rom, options, glyphs).expect("Error running in the terminal!");
+        if let Some(fault) = cpu.fault {
+            eprintln!("{}", fault);
+        }
+        cpu
+    } else {
+        window_run(rom_name, options)
+    };
+    finish_trace(&mut cpu);
+
+    if let (Some(mut movie), Some(path)) = (cpu.recording.take(), movie_file) {
+        movie.finish(&cpu);
+        fs::write(path, movie.format()).expect("Error writing movie file!");
+    }
+
+    if let Some(profiler) = &cpu.profiler {
+        if profile_report {
+            print!("{}", profiler.report(cpu.memory()));
+        }
+        if let Some(path) = profile_csv {
+            profiler.write_csv(path).expect("Error writing profile!");
+        }
+    }
+
+    if let Some(callgraph) = &cpu.callgraph {
+        let names = Names::new(cpu.rom(), &symbols);
+
+        if callgraph_report {
+            print!("{}", callgraph.report(&names));
+        }
+        if let Some(path) = callgraph_file {
+            callgraph
+                .write_folded(path, &names)
+                .expect("Error writing call graph!");
+        }
+    }
+
+    if let (Some(coverage), Some(path)) = (&cpu.coverage, coverage_file) {
+        let rom = cpu.rom();
+
+        print!("{}", coverage.summary(rom.len()));
+        coverage
+            .write_listing(path, rom)
+            .expect("Error writing coverage listing!");
+    }
+
+    if let Some(outcome) = outcome {
+        let screen = dump.render(&cpu);
+        match out_file {
+            Some(path) => fs::write(path, screen).expect("Error writing screen dump!"),
+            None => print!("{}", String::from_utf8_lossy(&screen)),
+        }
+
+        let summary = Summary::new(&cpu, outcome).to_json();
+        match summary_file {
+            Some(path) => fs::write(path, summary).expect("Error writing summary!"),
+            None => println!("{}", summary),
+        }
+
+        // Exiting skips destructors, the machine has to close its files first
+        drop(cpu);
+        process::exit(outcome.exit_code());
+    }
+}
+
+fn script_command(args: &[String]) {
+    let mut quirks = Quirks::default();
+    let mut instr_per_secs = INSTR_PER_SECS;
+    let mut seed = headless::DEFAULT_SEED;
+    let mut out_dir = Path::new(".");
+    let mut trace_file = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = parse_or_exit(Quirks::from_name(next_arg(&mut args))),
+            "--speed" => instr_per_secs = parse_or_exit(next_arg(&mut args).parse().ok()),
+            "--seed" => seed = parse_or_exit(next_arg(&mut args).parse().ok()),
+            "--out" => out_dir = Path::new(next_arg(&mut args)),
+            "--trace" => trace_file = Some(next_arg(&mut args)),
+            _ => files.push(arg.as_str()),
+        }
+    }
+
+    let [rom_name, script_name] = files[..] else {
+        exit_with_usage()
+    };
+    let rom = fs::read(rom_name).expect("Error reading rom file!");
+    let source = fs::read_to_string(script_name).expect("Error reading script file!");
+    let statements = script::parse(&source).unwrap_or_else(|err| {
+        eprintln!("{}:{}", script_name, err);
+        process::exit(1);
+    });
+
+    let mut cpu = Chip8::from_rom(&rom, quirks);
+    cpu.set_seed(seed);
+    if let Some(trace_file) = trace_file {
+        cpu.tracer = Some(
+            Tracer::to_file(trace_file, TraceFilter::default())
+                .expect("Error creating trace file!"),
+        );
+    }
+    let mut runner = Runner::new(&mut cpu, instr_per_secs);
+    let result = runner.run(&statements);
+
+    for (name, screen) in &runner.screenshots {
+        let path = out_dir.join(format!("{}.txt", name));
+        fs::write(&path, screen.to_ascii()).expect("Error writing screenshot!");
+    }
+    finish_trace(&mut cpu);
+
+    if let Err(err) = result {
+        eprintln!("{}:{}", script_name, err);
+        process::exit(1);
+    }
+}
+
+/// Writes out the rest of the trace, a run that lost part of it still ends
+fn finish_trace(cpu: &mut Chip8) {
+    if let Some(tracer) = cpu.tracer.take() {
+        if let Err(err) = tracer.finish() {
+            eprintln!("Error writing trace: {}", err);
+        }
+    }
+}
+
+fn next_arg<'a>(args: &mut impl Iterator<Item = &'a String>) -> &'a str {
+    args.next().unwrap_or_else(|| exit_with_usage())
+}
+
+/// `file` or the default config file, exits if it is invalid
+fn load_config(file: Option<PathBuf>) -> Config {
+    match file.or_else(Config::default_path) {
+        Some(path) => Config::load(&path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(1);
+        }),
+        None => Config::default(),
+    }
+}
+
+/// Shows the ROM menu and runs the picked ROMs until the window is closed
+#[cfg(feature = "sdl")]
+fn menu_command() {
+    let config = load_config(None);
+    let recent_file = Recent::default_path();
+    let mut recent = recent_file.as_deref().map(Recent::load).unwrap_or_default();
+    let mut externals = Externals::new();
+
+    loop {
+        let mut menu = Menu::new(&config.rom_dir(), &config, &recent);
+        let Some(entry) = menu.run(&mut externals) else {
+            break;
+        };
+
+        recent.add(&entry.path);
+        if let Some(path) = &recent_file {
+            if let Err(err) = recent.save(path) {
+                eprintln!("{}: {}", path.display(), err);
+            }
+        }
+
+        let rom_name = entry.path.to_string_lossy();
+        let options = RunOptions {
+            instr_per_secs: INSTR_PER_SECS,
+            quirks: Quirks::from_name(&entry.platform).unwrap_or_default(),
+            palette: config.palette_for(&rom_name, None).unwrap_or_default(),
+            palettes: config.palettes().unwrap_or_default(),
+            rotation: config.rotation_for(&rom_name, None),
+            ..Default::default()
+        };
+        let (mut cpu, exit) = window::window_loop(&rom_name, options, externals);
+        externals = cpu.externals.take().expect("The window is gone!");
+        if exit == Exit::Closed {
+            break;
+        }
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn menu_command() {
+    exit_without_window();
+}
+
+#[cfg(feature = "sdl")]
+fn window_run(rom_name: &str, options: RunOptions) -> Chip8 {
+    window::main_chip_loop(rom_name, options)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn window_run(_rom_name: &str, _options: RunOptions) -> Chip8 {
+    exit_without_window();
+}
+
+#[cfg(not(feature = "sdl"))]
+fn exit_without_window() -> ! {
+    eprintln!("Built without the sdl feature, only --headless and --terminal runs work");
+    process::exit(1);
+}
+
+fn parse_or_exit<T>(value: Option<T>) -> T {
+    value.unwrap_or_else(|| exit_with_usage())
+}
+
 fn main() {
-    chip::main_chip_loop("roms/2-ibm-logo.ch8", INSTR_PER_SECS);
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => disasm_command(&args[2..]),
+        Some("asm") => asm_command(&args[2..]),
+        Some("run") => run_command(&args[2..]),
+        Some("script") => script_command(&args[2..]),
+        Some(_) => exit_with_usage(),
+        None => menu_command(),
+    }
 }
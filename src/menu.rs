@@ -0,0 +1,385 @@
+//! ROM menu, shown in the window when the emulator starts without arguments.
+//! Lists the recently played ROMs followed by the ROMs in the configured
+//! directory, with titles and platforms from the config file or guessed from
+//! the file names.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::config::Config;
+use crate::display::Display;
+use crate::input::MenuAction;
+use crate::window::Externals;
+
+/// File extensions of CHIP-8, SUPER-CHIP and XO-CHIP ROMs
+pub const EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+/// How many recently played ROMs are remembered
+const RECENT_LIMIT: usize = 10;
+
+const TEXT_SCALE: u32 = 3;
+const MARGIN: i32 = 24;
+const LINE_HEIGHT: i32 = 24;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub title: String,
+    /// One of `quirks::PROFILES`
+    pub platform: String,
+}
+
+impl Entry {
+    /// Title and platform from the ROM's config section, else from its file
+    /// name and extension
+    pub fn new(path: &Path, config: &Config) -> Self {
+        let rom = path.to_str().and_then(|path| config.rom(path));
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+        Self {
+            path: path.to_path_buf(),
+            title: rom
+                .and_then(|rom| rom.title.clone())
+                .unwrap_or_else(|| title_from_file_name(&stem)),
+            platform: rom
+                .and_then(|rom| rom.platform.clone())
+                .unwrap_or_else(|| platform_for_extension(&extension).to_string()),
+        }
+    }
+}
+
+/// `2-ibm-logo` becomes `Ibm Logo`
+pub fn title_from_file_name(stem: &str) -> String {
+    let name = stem.trim_start_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '_');
+    let name = if name.is_empty() { stem } else { name };
+
+    name.split(['-', '_', ' '])
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap_or_default().to_uppercase();
+            first.chain(chars).collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The quirks profile that suits a ROM's file extension
+pub fn platform_for_extension(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "sc8" => "superchip",
+        "xo8" => "xochip",
+        _ => "chip8",
+    }
+}
+
+/// The ROMs in `dir`, sorted by title
+pub fn scan(dir: &Path, config: &Config) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        if path.is_file() && EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()) {
+            entries.push(Entry::new(&path, config));
+        }
+    }
+    entries.sort_by_key(|entry| (entry.title.to_lowercase(), entry.path.clone()));
+
+    Ok(entries)
+}
+
+/// Recently played ROMs, most recent first, one path per line in the file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recent {
+    pub paths: Vec<PathBuf>,
+}
+
+impl Recent {
+    /// `$XDG_STATE_HOME/chip8/recent` or `~/.local/state/chip8/recent`
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+
+        Some(dir.join("chip8").join("recent"))
+    }
+
+    /// Reads `path`, a missing or unreadable file is an empty list
+    pub fn load(path: &Path) -> Self {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        Self {
+            paths: text.lines().map(PathBuf::from).collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lines: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect();
+        fs::write(path, lines.join("\n") + "\n")
+    }
+
+    /// Moves `rom` to the front
+    pub fn add(&mut self, rom: &Path) {
+        self.paths.retain(|path| path != rom);
+        self.paths.insert(0, rom.to_path_buf());
+        self.paths.truncate(RECENT_LIMIT);
+    }
+}
+
+/// A row of the menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Line {
+    Heading(&'static str),
+    Entry(usize),
+}
+
+pub struct Menu {
+    dir: PathBuf,
+    /// Recent ROMs that still exist, then the rest of the directory
+    entries: Vec<Entry>,
+    recent: usize,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(dir: &Path, config: &Config, recent: &Recent) -> Self {
+        let mut entries: Vec<Entry> = recent
+            .paths
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| Entry::new(path, config))
+            .collect();
+        let recent = entries.len();
+
+        let in_dir = scan(dir, config).unwrap_or_else(|err| {
+            eprintln!("{}: {}", dir.display(), err);
+            Vec::new()
+        });
+        for entry in in_dir {
+            if !entries[..recent]
+                .iter()
+                .any(|e| same_file(&e.path, &entry.path))
+            {
+                entries.push(entry);
+            }
+        }
+
+        Self {
+            dir: dir.to_path_buf(),
+            entries,
+            recent,
+            selected: 0,
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.selected)
+    }
+
+    /// Moves the selection by `rows`, stopping at the ends
+    pub fn move_by(&mut self, rows: isize) {
+        let last = self.entries.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(rows).min(last);
+    }
+
+    /// Shows the menu until a ROM is picked, `None` once the player leaves
+    pub fn run(&mut self, externals: &mut Externals) -> Option<Entry> {
+        let frame_time = Duration::from_secs_f32(1.0 / 60.0);
+
+        loop {
+            match externals.input.menu_input() {
+                MenuAction::Move(rows) => self.move_by(rows),
+                MenuAction::First => self.selected = 0,
+                MenuAction::Last => self.move_by(isize::MAX),
+                MenuAction::Select => {
+                    if let Some(entry) = self.selected() {
+                        return Some(entry.clone());
+                    }
+                }
+                MenuAction::Back | MenuAction::Quit => return None,
+                MenuAction::ToggleFullscreen => externals.display.toggle_fullscreen(),
+                MenuAction::None => {}
+            }
+
+            self.draw(&mut externals.display);
+            externals.display.present();
+            thread::sleep(frame_time);
+        }
+    }
+
+    /// Headings and entries in the order they are drawn
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        if self.recent > 0 {
+            lines.push(Line::Heading("Recent"));
+            lines.extend((0..self.recent).map(Line::Entry));
+        }
+        if self.entries.len() > self.recent {
+            lines.push(Line::Heading("ROMs"));
+            lines.extend((self.recent..self.entries.len()).map(Line::Entry));
+        }
+        lines
+    }
+
+    fn draw(&self, display: &mut Display) {
+        let [r, g, b] = display.palette.off();
+        let background = Color::RGB(r, g, b);
+        let [r, g, b] = display.palette.on();
+        let foreground = Color::RGB(r, g, b);
+        let (width, height) = display.canvas.output_size().unwrap_or((640, 320));
+
+        display.fill_rect(Rect::new(0, 0, width, height), background);
+        let title = format!("CHIP-8  {}", self.dir.display());
+        display.draw_text(MARGIN, MARGIN, TEXT_SCALE, &title, foreground);
+
+        let top = MARGIN + LINE_HEIGHT * 2;
+        let lines = self.lines();
+        if lines.is_empty() {
+            let text = "No ROMs found, set rom_dir in the config file";
+            display.draw_text(MARGIN, top, TEXT_SCALE, text, foreground);
+            return;
+        }
+
+        // Keep the selection in the middle once the list is longer than the
+        // window
+        let rows = ((height as i32 - top - MARGIN) / LINE_HEIGHT).max(1) as usize;
+        let selected_line = lines
+            .iter()
+            .position(|line| *line == Line::Entry(self.selected))
+            .unwrap_or(0);
+        let first = selected_line
+            .saturating_sub(rows / 2)
+            .min(lines.len().saturating_sub(rows));
+
+        for (row, line) in lines.iter().skip(first).take(rows).enumerate() {
+            let y = top + row as i32 * LINE_HEIGHT;
+            match *line {
+                Line::Heading(text) => {
+                    display.draw_text(MARGIN, y, TEXT_SCALE, text, foreground);
+                }
+                Line::Entry(index) => {
+                    let entry = &self.entries[index];
+                    let color = if index == self.selected {
+                        let bar = Rect::new(
+                            MARGIN,
+                            y - 4,
+                            width.saturating_sub(2 * MARGIN as u32),
+                            LINE_HEIGHT as u32,
+                        );
+                        display.fill_rect(bar, foreground);
+                        background
+                    } else {
+                        foreground
+                    };
+
+                    let x = MARGIN + 2 * LINE_HEIGHT;
+                    display.draw_text(x, y, TEXT_SCALE, &entry.title, color);
+                    let (platform_width, _) = Display::text_size(&entry.platform, TEXT_SCALE);
+                    let x = width as i32 - 2 * MARGIN - platform_width as i32;
+                    display.draw_text(x, y, TEXT_SCALE, &entry.platform, color);
+                }
+            }
+        }
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn titles_from_file_names() {
+        assert_eq!(title_from_file_name("2-ibm-logo"), "Ibm Logo");
+        assert_eq!(title_from_file_name("br8kout"), "Br8kout");
+        assert_eq!(title_from_file_name("space_invaders"), "Space Invaders");
+        assert_eq!(title_from_file_name("1234"), "1234");
+    }
+
+    #[test]
+    fn entries_from_config_or_file_name() {
+        let config = Config::parse(
+            r#"
+            [roms."br8kout.ch8"]
+            title = "Breakout"
+            platform = "chip8"
+            "#,
+        )
+        .unwrap();
+
+        let entry = Entry::new(Path::new("roms/br8kout.ch8"), &config);
+        assert_eq!(
+            (entry.title.as_str(), entry.platform.as_str()),
+            ("Breakout", "chip8")
+        );
+
+        let entry = Entry::new(Path::new("games/alien-inv8sion.xo8"), &config);
+        assert_eq!(entry.title, "Alien Inv8sion");
+        assert_eq!(entry.platform, "xochip");
+    }
+
+    #[test]
+    fn recent_files() {
+        let mut recent = Recent::default();
+        for n in 0..12 {
+            recent.add(Path::new(&format!("{}.ch8", n)));
+        }
+        recent.add(Path::new("5.ch8"));
+
+        assert_eq!(recent.paths.len(), RECENT_LIMIT);
+        assert_eq!(recent.paths[0], Path::new("5.ch8"));
+        assert_eq!(recent.paths[1], Path::new("11.ch8"));
+        assert_eq!(
+            recent.paths.iter().filter(|p| p.ends_with("5.ch8")).count(),
+            1
+        );
+
+        let path = std::env::temp_dir().join(format!("chip8-recent-{}", std::process::id()));
+        recent.save(&path).unwrap();
+        assert_eq!(Recent::load(&path), recent);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn menu_lists_recent_roms_first() {
+        let recent = Recent {
+            paths: vec![PathBuf::from("roms/ibm.ch8"), PathBuf::from("missing.ch8")],
+        };
+        let mut menu = Menu::new(Path::new("roms"), &Config::default(), &recent);
+
+        assert_eq!(menu.entries()[0].path, Path::new("roms/ibm.ch8"));
+        let ibms = menu.entries().iter().filter(|e| e.title == "Ibm").count();
+        assert_eq!(ibms, 1);
+        assert_eq!(menu.lines()[0], Line::Heading("Recent"));
+        assert_eq!(menu.lines()[2], Line::Heading("ROMs"));
+
+        menu.move_by(-3);
+        assert_eq!(menu.selected().unwrap().title, "Ibm");
+        menu.move_by(isize::MAX);
+        assert_eq!(menu.selected, menu.entries().len() - 1);
+    }
+}
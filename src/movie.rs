@@ -0,0 +1,265 @@
+//! Input movies: the key pad state of every frame plus everything else a run
+//! depends on, so that replaying it reproduces the run exactly.
+//!
+//! Movies are text files:
+//!
+//! ```text
+//! chip8-movie 1
+//! rom 8b3c5a6f12d09e47
+//! seed 1234
+//! quirks vf_reset memory_increment display_wait clipping
+//! speed 700
+//! state 0f8e2d6c1b4a3957
+//! frames 3
+//! 0000
+//! 0020
+//! 0000
+//! ```
+//!
+//! `rom` is the FNV-1a hash of the ROM image, `speed` the instructions per
+//! second and `state` the machine state hash after the last frame, which a
+//! replay compares against. Each frame is a 16 bit hex mask, bit N is key N.
+
+use std::fmt;
+
+use crate::chip::{Chip8, FrameBudget};
+use crate::quirks::Quirks;
+
+const MAGIC: &str = "chip8-movie 1";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieError {
+    /// 0 for problems with the file as a whole
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instr_per_secs: f32,
+    /// Machine state hash after the last frame, once the recording is done
+    pub end_state: Option<u64>,
+    /// Key pad mask per frame
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, quirks: Quirks, instr_per_secs: f32) -> Self {
+        Self {
+            rom_hash: hash(rom),
+            seed,
+            quirks,
+            instr_per_secs,
+            end_state: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, key_pad: &[bool; 16]) {
+        self.frames.push(key_mask(key_pad));
+    }
+
+    /// Key pad state for `frame`, `None` once the movie has ended
+    pub fn key_pad(&self, frame: usize) -> Option<[bool; 16]> {
+        self.frames.get(frame).map(|mask| key_pad(*mask))
+    }
+
+    /// Stores the state the recording ended in
+    pub fn finish(&mut self, chip: &Chip8) {
+        self.end_state = Some(chip.state_hash());
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == hash(rom)
+    }
+
+    /// Whether `chip`, having replayed the whole movie, ended up where the
+    /// recording did. Movies without an end state always match.
+    pub fn matches_end_state(&self, chip: &Chip8) -> bool {
+        self.end_state
+            .is_none_or(|state| state == chip.state_hash())
+    }
+
+    /// Sets `chip` up like the recording. Call before the first frame.
+    pub fn prepare(&self, chip: &mut Chip8) {
+        chip.set_seed(self.seed);
+        chip.quirks = self.quirks;
+    }
+
+    /// Replays every frame headlessly on a freshly loaded `chip`
+    pub fn play(&self, chip: &mut Chip8) {
+        self.prepare(chip);
+        let mut budget = FrameBudget::new(self.instr_per_secs);
+
+        for mask in &self.frames {
+            chip.key_pad = key_pad(*mask);
+            chip.run_frame(budget.take_frame());
+        }
+    }
+
+    pub fn format(&self) -> String {
+        let mut out = format!("{}\n", MAGIC);
+        out.push_str(&format!("rom {:016x}\n", self.rom_hash));
+        out.push_str(&format!("seed {}\n", self.seed));
+        out.push_str(&format!("quirks {}\n", self.quirks.enabled().join(" ")));
+        out.push_str(&format!("speed {}\n", self.instr_per_secs));
+        if let Some(state) = self.end_state {
+            out.push_str(&format!("state {:016x}\n", state));
+        }
+
+        out.push_str(&format!("frames {}\n", self.frames.len()));
+        for mask in &self.frames {
+            out.push_str(&format!("{:04x}\n", mask));
+        }
+
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()));
+        let error = |line: usize, message: String| MovieError { line, message };
+
+        match lines.next() {
+            Some((_, MAGIC)) => {}
+            _ => return Err(error(1, "not a movie file".to_string())),
+        }
+
+        let mut movie = Movie::new(&[], 0, Quirks::default(), 0.0);
+        let mut rom_hash = None;
+        let mut frame_count = None;
+
+        for (number, line) in lines.by_ref() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let bad_value = || error(number, format!("invalid {} '{}'", key, value));
+
+            match key {
+                "rom" => rom_hash = Some(u64::from_str_radix(value, 16).map_err(|_| bad_value())?),
+                "seed" => movie.seed = value.parse().map_err(|_| bad_value())?,
+                "quirks" => {
+                    movie.quirks =
+                        Quirks::from_enabled(value.split_whitespace()).ok_or_else(bad_value)?
+                }
+                "speed" => movie.instr_per_secs = value.parse().map_err(|_| bad_value())?,
+                "state" => {
+                    movie.end_state = Some(u64::from_str_radix(value, 16).map_err(|_| bad_value())?)
+                }
+                "frames" => {
+                    frame_count = Some(value.parse::<usize>().map_err(|_| bad_value())?);
+                    break;
+                }
+                _ => return Err(error(number, format!("unknown field '{}'", key))),
+            }
+        }
+
+        movie.rom_hash = rom_hash.ok_or_else(|| error(0, "missing rom hash".to_string()))?;
+        let frame_count = frame_count.ok_or_else(|| error(0, "missing frames".to_string()))?;
+
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let mask = u16::from_str_radix(line, 16)
+                .map_err(|_| error(number, format!("invalid key mask '{}'", line)))?;
+            movie.frames.push(mask);
+        }
+
+        if movie.frames.len() != frame_count {
+            return Err(error(
+                0,
+                format!(
+                    "expected {} frames, found {}",
+                    frame_count,
+                    movie.frames.len()
+                ),
+            ));
+        }
+
+        Ok(movie)
+    }
+}
+
+/// 64 bit FNV-1a, stable across platforms and Rust versions
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn key_mask(key_pad: &[bool; 16]) -> u16 {
+    key_pad
+        .iter()
+        .enumerate()
+        .filter(|(_, down)| **down)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+pub fn key_pad(mask: u16) -> [bool; 16] {
+    let mut key_pad = [false; 16];
+    for (key, down) in key_pad.iter_mut().enumerate() {
+        *down = mask & (1 << key) != 0;
+    }
+    key_pad
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_masks() {
+        let mut keys = [false; 16];
+        keys[0] = true;
+        keys[0xf] = true;
+
+        assert_eq!(key_mask(&keys), 0x8001);
+        assert_eq!(key_pad(0x8001), keys);
+    }
+
+    #[test]
+    fn format_and_parse() {
+        let mut movie = Movie::new(b"rom", 42, Quirks::chip8(), 700.0);
+        movie.record(&key_pad(0x0020));
+        movie.record(&key_pad(0));
+        movie.end_state = Some(0xabc);
+
+        let text = movie.format();
+        assert!(text.starts_with("chip8-movie 1\nrom "));
+        assert!(text.contains("\nquirks vf_reset memory_increment display_wait clipping\n"));
+        assert!(text.ends_with("state 0000000000000abc\nframes 2\n0020\n0000\n"));
+
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed, movie);
+        assert!(parsed.matches_rom(b"rom"));
+        assert!(!parsed.matches_rom(b"other"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Movie::parse(text).unwrap_err().to_string();
+
+        assert_eq!(error("hello"), "line 1: not a movie file");
+        assert_eq!(
+            error("chip8-movie 1\nrom 1\nseed x\n"),
+            "line 3: invalid seed 'x'"
+        );
+        assert_eq!(
+            error("chip8-movie 1\nrom 1\nframes 2\n0001\n"),
+            "expected 2 frames, found 1"
+        );
+    }
+}
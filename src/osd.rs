@@ -0,0 +1,166 @@
+//! On-screen display: short messages drawn over the game, and the status
+//! shown in the window title.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::display::Display;
+
+/// How long a message stays, in frames
+const MESSAGE_FRAMES: u32 = 120;
+/// Older messages make room beyond this many
+const MAX_MESSAGES: usize = 4;
+const MARGIN: i32 = 8;
+const PADDING: i32 = 4;
+
+const BACKGROUND_COLOR: Color = Color::RGB(0x1c, 0x1c, 0x1c);
+const TEXT_COLOR: Color = Color::RGB(0xff, 0xcc, 0x01);
+
+/// Messages in the bottom left corner of the game, newest at the bottom
+#[derive(Default)]
+pub struct Messages {
+    /// Text and the frames it has left
+    queue: VecDeque<(String, u32)>,
+}
+
+impl Messages {
+    pub fn show(&mut self, text: impl Into<String>) {
+        if self.queue.len() == MAX_MESSAGES {
+            self.queue.pop_front();
+        }
+        self.queue.push_back((text.into(), MESSAGE_FRAMES));
+    }
+
+    /// Called once per frame, drops the messages that have been shown long
+    /// enough
+    pub fn tick(&mut self) {
+        for (_, frames) in self.queue.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+        self.queue.retain(|(_, frames)| *frames > 0);
+    }
+
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.queue.iter().map(|(text, _)| text.as_str())
+    }
+
+    pub fn draw(&self, display: &mut Display) {
+        let area = display.game_area();
+        let scale = (area.height() / 200).max(2);
+        let line_height = Display::text_size("", scale).1 as i32 + 2 * PADDING + 2;
+        let mut y = area.bottom() - MARGIN - line_height * self.queue.len() as i32;
+
+        for text in self.texts() {
+            let (width, height) = Display::text_size(text, scale);
+            let background = Rect::new(
+                area.x() + MARGIN,
+                y,
+                width + 2 * PADDING as u32,
+                height + 2 * PADDING as u32,
+            );
+            display.fill_rect(background, BACKGROUND_COLOR);
+            display.draw_text(
+                background.x() + PADDING,
+                y + PADDING,
+                scale,
+                text,
+                TEXT_COLOR,
+            );
+            y += line_height;
+        }
+    }
+}
+
+/// Frames and instructions per second, measured over the last second
+pub struct Status {
+    since: Instant,
+    frames: u32,
+    instructions: u64,
+    pub fps: u32,
+    pub ips: u64,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            frames: 0,
+            instructions: 0,
+            fps: 0,
+            ips: 0,
+        }
+    }
+}
+
+impl Status {
+    /// Counts a frame that ran `instructions`. True when a second is over and
+    /// the rates are new.
+    pub fn frame(&mut self, instructions: u32) -> bool {
+        self.frames += 1;
+        self.instructions += u64::from(instructions);
+
+        let elapsed = self.since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+
+        let secs = elapsed.as_secs_f64();
+        self.fps = (f64::from(self.frames) / secs).round() as u32;
+        self.ips = (self.instructions as f64 / secs).round() as u64;
+        self.since = Instant::now();
+        self.frames = 0;
+        self.instructions = 0;
+
+        true
+    }
+}
+
+/// `pong.ch8 - chip8 - 60 FPS - 700 IPS`
+pub fn title(rom: &str, profile: &str, status: &Status) -> String {
+    format!(
+        "{} - {} - {} FPS - {} IPS",
+        rom, profile, status.fps, status.ips
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages_expire() {
+        let mut messages = Messages::default();
+        messages.show("Paused");
+        for _ in 0..MESSAGE_FRAMES / 2 {
+            messages.tick();
+        }
+        messages.show("Speed 2x");
+        assert_eq!(messages.texts().collect::<Vec<_>>(), ["Paused", "Speed 2x"]);
+
+        for _ in 0..MESSAGE_FRAMES / 2 {
+            messages.tick();
+        }
+        assert_eq!(messages.texts().collect::<Vec<_>>(), ["Speed 2x"]);
+
+        for i in 0..MAX_MESSAGES {
+            messages.show(format!("Message {}", i));
+        }
+        assert_eq!(messages.texts().next(), Some("Message 0"));
+    }
+
+    #[test]
+    fn title_with_rates() {
+        let status = Status {
+            fps: 60,
+            ips: 700,
+            ..Default::default()
+        };
+        assert_eq!(
+            title("pong.ch8", "chip8", &status),
+            "pong.ch8 - chip8 - 60 FPS - 700 IPS"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+
+use sdl2::pixels::Color;
+
+use crate::chip::Chip8;
+use crate::disasm;
+use crate::display::Display;
+
+const TEXT_SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = 14;
+const MARGIN: i32 = 10;
+
+const DISASM_BEFORE: u16 = 4; // Instructions shown before PC
+const DISASM_AFTER: usize = 8; // Instructions shown from PC on
+const MEMORY_ROWS: u16 = 8;
+const MEMORY_ROW_BYTES: u16 = 8;
+
+const BACKGROUND_COLOR: Color = Color::RGB(0x1c, 0x1c, 0x1c);
+const TEXT_COLOR: Color = Color::RGB(0xff, 0xcc, 0x01);
+const DIM_COLOR: Color = Color::RGB(0x99, 0x66, 0x01);
+const HIGHLIGHT_COLOR: Color = Color::RGB(0xff, 0xff, 0xff);
+
+/// Key pad layout as printed on the COSMAC VIP
+const KEY_PAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xc],
+    [0x4, 0x5, 0x6, 0xd],
+    [0x7, 0x8, 0x9, 0xe],
+    [0xa, 0x0, 0xb, 0xf],
+];
+
+/// Side panel with the machine state, drawn right of the game.
+#[derive(Default)]
+pub struct DebugOverlay {
+    pub visible: bool,
+    /// Start of the memory view, `None` follows I
+    memory_addr: Option<u16>,
+}
+
+impl DebugOverlay {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Scrolls the memory view by whole pages, starting from I, up to the
+    /// last page of the `memory_len` bytes of memory.
+    pub fn scroll_memory(&mut self, pages: i32, ireg: u16, memory_len: usize) {
+        let start = i32::from(self.memory_start(ireg));
+        let page = i32::from(MEMORY_ROWS * MEMORY_ROW_BYTES);
+        let last = (memory_len.max(1) as i32 - 1) & !(page - 1);
+        let start = (start + pages * page).clamp(0, last);
+
+        self.memory_addr = Some(start as u16);
+    }
+
+    pub fn follow_index(&mut self) {
+        self.memory_addr = None;
+    }
+
+    fn memory_start(&self, ireg: u16) -> u16 {
+        self.memory_addr.unwrap_or(ireg & !(MEMORY_ROW_BYTES - 1))
+    }
+
+    pub fn draw(&self, display: &mut Display, chip: &Chip8) {
+        let panel = display.panel_rect();
+        display.fill_rect(panel, BACKGROUND_COLOR);
+
+        let mut cursor = Cursor {
+            display,
+            x: panel.x() + MARGIN,
+            y: MARGIN,
+        };
+
+        for line in state_lines(chip) {
+            cursor.line(&line, TEXT_COLOR);
+        }
+
+        cursor.gap();
+        cursor.line("KEYS", DIM_COLOR);
+        for row in KEY_PAD {
+            for (col, key) in row.iter().enumerate() {
+                let color = if chip.key_pad[usize::from(*key)] {
+                    HIGHLIGHT_COLOR
+                } else {
+                    DIM_COLOR
+                };
+                cursor.text(col as i32 * 2, &format!("{:X}", key), color);
+            }
+            cursor.y += LINE_HEIGHT;
+        }
+
+        cursor.gap();
+        cursor.line("CODE", DIM_COLOR);
+        for (addr, line) in disasm_lines(chip.memory(), chip.pc()) {
+            let color = if addr == chip.pc() {
+                HIGHLIGHT_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            cursor.line(&line, color);
+        }
+
+        cursor.gap();
+        cursor.line("MEMORY", DIM_COLOR);
+        for line in memory_lines(chip.memory(), self.memory_start(chip.ireg())) {
+            cursor.line(&line, TEXT_COLOR);
+        }
+    }
+}
+
+struct Cursor<'a> {
+    display: &'a mut Display,
+    x: i32,
+    y: i32,
+}
+
+impl Cursor<'_> {
+    fn text(&mut self, column: i32, text: &str, color: Color) {
+        let column_width = 4 * TEXT_SCALE as i32;
+        self.display.draw_text(
+            self.x + column * column_width,
+            self.y,
+            TEXT_SCALE,
+            text,
+            color,
+        );
+    }
+
+    fn line(&mut self, text: &str, color: Color) {
+        self.text(0, text, color);
+        self.y += LINE_HEIGHT;
+    }
+
+    fn gap(&mut self) {
+        self.y += LINE_HEIGHT / 2;
+    }
+}
+
+/// Registers, timers and the stack.
+fn state_lines(chip: &Chip8) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "PC {:#06X}  I {:#06X}  SP {}",
+            chip.pc(),
+            chip.ireg(),
+            chip.stack.len()
+        ),
+        format!(
+            "DT {:<3}  ST {:<3}  FRAME {}",
+            chip.delay_timer, chip.sound_timer, chip.frame
+        ),
+    ];
+
+    for (row, values) in chip.registers().chunks(4).enumerate() {
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X} {:02X}", row * 4 + i, v))
+            .collect();
+        lines.push(cells.join("  "));
+    }
+
+    let stack: Vec<String> = chip
+        .stack
+        .iter()
+        .rev()
+        .map(|addr| format!("{:03X}", addr))
+        .collect();
+    lines.push(format!("STACK {}", stack.join(" ")));
+
+    lines
+}
+
+/// Disassembly around `pc`, marked with `>`. Instructions before PC are
+/// decoded separately so PC always starts an instruction.
+fn disasm_lines(memory: &[u8], pc: u16) -> Vec<(u16, String)> {
+    let before = pc.saturating_sub(DISASM_BEFORE * 2);
+    let pc_index = usize::from(pc).min(memory.len());
+    let before_index = usize::from(before).min(pc_index);
+
+    let mut lines = disasm::decode_from(&memory[before_index..pc_index], before);
+    let after = disasm::decode_from(&memory[pc_index..], pc);
+    lines.extend(after.into_iter().take(DISASM_AFTER));
+
+    let labels = BTreeMap::new();
+    lines
+        .iter()
+        .map(|line| {
+            let marker = if line.addr == pc { '>' } else { ' ' };
+            let text = match line.instr {
+                Some(instr) => disasm::classic(&instr, line.long, &labels),
+                None => format!("DB {:#04X}", line.bytes[0]),
+            };
+            (line.addr, format!("{}{:03X} {}", marker, line.addr, text))
+        })
+        .collect()
+}
+
+/// Hex dump starting at `start`, bytes past the end of memory show as `--`.
+fn memory_lines(memory: &[u8], start: u16) -> Vec<String> {
+    (0..MEMORY_ROWS)
+        .map(|row| {
+            let addr = usize::from(start) + usize::from(row * MEMORY_ROW_BYTES);
+            let bytes: Vec<String> = (addr..addr + usize::from(MEMORY_ROW_BYTES))
+                .map(|addr| match memory.get(addr) {
+                    Some(byte) => format!("{:02X}", byte),
+                    None => "--".to_string(),
+                })
+                .collect();
+            format!("{:03X} {}", addr, bytes.join(" "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disassembly_is_anchored_at_pc() {
+        let mut memory = vec![0; 0x200];
+        memory.extend([0x00, 0xe0, 0x61, 0x11, 0x12, 0x02]);
+
+        let lines = disasm_lines(&memory, 0x202);
+
+        assert_eq!(lines[3], (0x200, " 200 CLS".to_string()));
+        assert_eq!(lines[4], (0x202, ">202 LD V1, 0x11".to_string()));
+        assert_eq!(lines[5], (0x204, " 204 JP 0x202".to_string()));
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[test]
+    fn memory_view_pads_past_the_end() {
+        let memory = [0xab; 0x204];
+        let lines = memory_lines(&memory, 0x200);
+
+        assert_eq!(lines[0], "200 AB AB AB AB -- -- -- --");
+        assert_eq!(lines.len(), usize::from(MEMORY_ROWS));
+    }
+
+    #[test]
+    fn memory_view_follows_i_until_scrolled() {
+        let mut overlay = DebugOverlay::default();
+        assert_eq!(overlay.memory_start(0x22b), 0x228);
+
+        overlay.scroll_memory(1, 0x22b, 0x1000);
+        assert_eq!(overlay.memory_start(0x300), 0x268);
+
+        overlay.scroll_memory(-100, 0, 0x1000);
+        assert_eq!(overlay.memory_start(0x300), 0);
+
+        overlay.scroll_memory(100, 0, 0x1000);
+        assert_eq!(overlay.memory_start(0x300), 0xfc0);
+
+        // Memory grows past 4K for large ROMs
+        overlay.scroll_memory(1, 0, 0x10000);
+        assert_eq!(overlay.memory_start(0x300), 0x1000);
+        overlay.scroll_memory(2000, 0, 0x10000);
+        assert_eq!(overlay.memory_start(0x300), 0xffc0);
+
+        overlay.follow_index();
+        assert_eq!(overlay.memory_start(0x300), 0x300);
+    }
+}
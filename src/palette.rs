@@ -0,0 +1,183 @@
+//! Display colors. A palette has 2 to 16 colors: color 0 is the background,
+//! color 1 is drawn for pixels that are on and the rest are for the extra
+//! XO-CHIP plane combinations.
+
+/// Most colors a palette can have, one per XO-CHIP plane combination
+pub const MAX_COLORS: usize = 16;
+
+/// Built-in themes, the first one is the default
+pub const THEMES: [&str; 5] = ["amber", "vip", "lcd", "high-contrast", "colorblind"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    /// RGB colors, always between 2 and `MAX_COLORS`
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::theme(THEMES[0]).unwrap()
+    }
+}
+
+impl Palette {
+    /// A palette from 2 to 16 colors
+    pub fn new(name: &str, colors: &[[u8; 3]]) -> Option<Self> {
+        if !(2..=MAX_COLORS).contains(&colors.len()) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            colors: colors.to_vec(),
+        })
+    }
+
+    /// Parses colors written as `#rrggbb` or `rrggbb`
+    pub fn parse(name: &str, colors: &[impl AsRef<str>]) -> Result<Self, String> {
+        let colors = colors
+            .iter()
+            .map(|color| parse_color(color.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::new(name, &colors).ok_or_else(|| {
+            format!(
+                "palette '{}' has {} colors, it needs 2 to {}",
+                name,
+                colors.len(),
+                MAX_COLORS
+            )
+        })
+    }
+
+    /// One of `THEMES`
+    pub fn theme(name: &str) -> Option<Self> {
+        let colors: &[[u8; 3]] = match name {
+            "amber" => &[[0x99, 0x66, 0x01], [0xff, 0xcc, 0x01]],
+            // Green phosphor of the COSMAC VIP's monitor
+            "vip" => &[
+                [0x0a, 0x1a, 0x0a],
+                [0x45, 0xff, 0x6a],
+                [0x20, 0x90, 0x38],
+                [0xb0, 0xff, 0xc0],
+            ],
+            // Dark pixels on a green reflective LCD
+            "lcd" => &[
+                [0x9b, 0xbc, 0x0f],
+                [0x0f, 0x38, 0x0f],
+                [0x8b, 0xac, 0x0f],
+                [0x30, 0x62, 0x30],
+            ],
+            "high-contrast" => &[
+                [0x00, 0x00, 0x00],
+                [0xff, 0xff, 0xff],
+                [0xff, 0xff, 0x00],
+                [0x00, 0xff, 0xff],
+            ],
+            // Okabe-Ito colors, distinguishable with every common color
+            // vision deficiency
+            "colorblind" => &[
+                [0x00, 0x00, 0x00],
+                [0xe6, 0x9f, 0x00],
+                [0x56, 0xb4, 0xe9],
+                [0xf0, 0xe4, 0x42],
+            ],
+            _ => return None,
+        };
+
+        Self::new(name, colors)
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    pub fn off(&self) -> [u8; 3] {
+        self.colors[0]
+    }
+
+    pub fn on(&self) -> [u8; 3] {
+        self.colors[1]
+    }
+
+    /// Color between `off` (0) and `on` (255)
+    pub fn shade(&self, level: u8) -> [u8; 3] {
+        let (off, on) = (self.off(), self.on());
+        let mix = |off: u8, on: u8| {
+            let (off, on, level) = (i32::from(off), i32::from(on), i32::from(level));
+            (off + (on - off) * level / 255) as u8
+        };
+
+        [mix(off[0], on[0]), mix(off[1], on[1]), mix(off[2], on[2])]
+    }
+}
+
+/// The palette after `current` in `palettes`, for cycling through them
+pub fn next<'a>(palettes: &'a [Palette], current: &Palette) -> Option<&'a Palette> {
+    let index = palettes.iter().position(|p| p.name == current.name);
+    let next = index.map_or(0, |i| (i + 1) % palettes.len());
+
+    palettes.get(next)
+}
+
+fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    let value = (hex.len() == 6)
+        .then(|| u32::from_str_radix(hex, 16).ok())
+        .flatten()
+        .ok_or_else(|| format!("invalid color '{}'", text))?;
+    let [_, r, g, b] = value.to_be_bytes();
+
+    Ok([r, g, b])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_theme_exists() {
+        for name in THEMES {
+            assert_eq!(Palette::theme(name).unwrap().name, name);
+        }
+        assert_eq!(Palette::default().name, "amber");
+    }
+
+    #[test]
+    fn parse_colors() {
+        let palette = Palette::parse("mine", &["#000000", "ff8000", "#0000FF"]).unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[[0, 0, 0], [0xff, 0x80, 0], [0, 0, 0xff]]
+        );
+
+        assert_eq!(
+            Palette::parse("x", &["#000000", "#12345"]),
+            Err("invalid color '#12345'".to_string())
+        );
+        assert_eq!(
+            Palette::parse("x", &["#000000"]),
+            Err("palette 'x' has 1 colors, it needs 2 to 16".to_string())
+        );
+    }
+
+    #[test]
+    fn shades_run_from_off_to_on() {
+        let palette = Palette::new("x", &[[0, 200, 10], [255, 0, 10]]).unwrap();
+
+        assert_eq!(palette.shade(0), palette.off());
+        assert_eq!(palette.shade(255), palette.on());
+        assert_eq!(palette.shade(51), [51, 160, 10]);
+    }
+
+    #[test]
+    fn cycling() {
+        let palettes: Vec<Palette> = THEMES.iter().filter_map(|t| Palette::theme(t)).collect();
+
+        assert_eq!(next(&palettes, &palettes[0]).unwrap().name, "vip");
+        assert_eq!(next(&palettes, &palettes[4]).unwrap().name, "amber");
+        let custom = Palette::new("custom", &[[0; 3], [1; 3]]).unwrap();
+        assert_eq!(next(&palettes, &custom).unwrap().name, "amber");
+    }
+}
@@ -0,0 +1,116 @@
+//! Phosphor persistence against the flicker of XOR redraws. Turns the on/off
+//! framebuffer into a brightness per pixel, from 0 (off color) to 255 (on
+//! color), that the display shades with `Palette::shade`.
+
+use crate::screen::Screen;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Persistence {
+    /// Pixels are either on or off
+    #[default]
+    Off,
+    /// Pixels that turn off fade out, keeping this fraction of their
+    /// brightness every frame
+    Decay(f32),
+    /// Pixels are lit if they are on in this frame or the one before
+    Blend,
+}
+
+impl Persistence {
+    /// `off`, `blend` or a decay fraction between 0 and 1
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Persistence::Off),
+            "blend" => Some(Persistence::Blend),
+            _ => name
+                .parse()
+                .ok()
+                .filter(|decay| (0.0..1.0).contains(decay))
+                .map(Persistence::Decay),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Phosphor {
+    pub persistence: Persistence,
+    /// Brightness per pixel between 0 and 1
+    brightness: Vec<f32>,
+    previous: Vec<u8>,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence) -> Self {
+        Self {
+            persistence,
+            ..Default::default()
+        }
+    }
+
+    /// Advances by one frame and returns the brightness of every pixel
+    pub fn update(&mut self, screen: &Screen) -> Vec<u8> {
+        let pixels = screen.pixels();
+        self.brightness.resize(pixels.len(), 0.0);
+        self.previous.resize(pixels.len(), 0);
+
+        let shades = match self.persistence {
+            Persistence::Off => pixels.iter().map(|on| on * 255).collect(),
+            Persistence::Blend => pixels
+                .iter()
+                .zip(&self.previous)
+                .map(|(on, was_on)| (on | was_on) * 255)
+                .collect(),
+            Persistence::Decay(decay) => pixels
+                .iter()
+                .zip(self.brightness.iter_mut())
+                .map(|(on, brightness)| {
+                    *brightness = if *on != 0 { 1.0 } else { *brightness * decay };
+                    (*brightness * 255.0) as u8
+                })
+                .collect(),
+        };
+
+        self.previous.copy_from_slice(pixels);
+        shades
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lit() -> Screen {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0x80], true);
+        screen
+    }
+
+    #[test]
+    fn decay_fades_pixels_out() {
+        let mut phosphor = Phosphor::new(Persistence::Decay(0.5));
+
+        assert_eq!(phosphor.update(&lit())[0], 255);
+        assert_eq!(phosphor.update(&Screen::new())[0], 127);
+        assert_eq!(phosphor.update(&Screen::new())[0], 63);
+        assert_eq!(phosphor.update(&lit())[0], 255);
+        assert_eq!(phosphor.update(&lit())[1], 0);
+    }
+
+    #[test]
+    fn blend_ors_the_last_two_frames() {
+        let mut phosphor = Phosphor::new(Persistence::Blend);
+
+        assert_eq!(phosphor.update(&lit())[0], 255);
+        assert_eq!(phosphor.update(&Screen::new())[0], 255);
+        assert_eq!(phosphor.update(&Screen::new())[0], 0);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Persistence::from_name("off"), Some(Persistence::Off));
+        assert_eq!(Persistence::from_name("blend"), Some(Persistence::Blend));
+        assert_eq!(Persistence::from_name("0.6"), Some(Persistence::Decay(0.6)));
+        assert_eq!(Persistence::from_name("1.5"), None);
+        assert_eq!(Persistence::from_name("fade"), None);
+    }
+}
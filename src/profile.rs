@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+use crate::disasm;
+
+const HOT_ADDRESSES: usize = 20; // Rows in the text report
+
+/// Counts executions per address and opcode class, and instructions and
+/// draw calls per frame.
+#[derive(Default)]
+pub struct Profiler {
+    pc_counts: HashMap<u16, u64>,
+    class_counts: [u64; 16],
+    /// Instructions and draw calls of every finished frame
+    frames: Vec<(u64, u64)>,
+    frame_instructions: u64,
+    frame_draws: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        *self.pc_counts.entry(pc).or_insert(0) += 1;
+        self.class_counts[usize::from(opcode >> 12)] += 1;
+        self.frame_instructions += 1;
+    }
+
+    pub fn record_draw(&mut self) {
+        self.frame_draws += 1;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames
+            .push((self.frame_instructions, self.frame_draws));
+        self.frame_instructions = 0;
+        self.frame_draws = 0;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.class_counts.iter().sum()
+    }
+
+    /// Addresses by execution count, most executed first
+    pub fn hot_addresses(&self) -> Vec<(u16, u64)> {
+        let mut counts: Vec<(u16, u64)> = self.pc_counts.iter().map(|(pc, n)| (*pc, *n)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// Human readable summary. `memory` is used to show the instruction at
+    /// each hot address.
+    pub fn report(&self, memory: &[u8]) -> String {
+        let total = self.total();
+        let mut out = String::new();
+
+        out.push_str(&format!(
+            "{} instructions over {} frames\n",
+            total,
+            self.frames.len()
+        ));
+        out.push_str(&format!(
+            "instructions/frame: avg {:.1} max {}\n",
+            self.average(|(instructions, _)| instructions),
+            self.frames.iter().map(|f| f.0).max().unwrap_or(0)
+        ));
+        out.push_str(&format!(
+            "draws/frame:        avg {:.1} max {}\n",
+            self.average(|(_, draws)| draws),
+            self.frames.iter().map(|f| f.1).max().unwrap_or(0)
+        ));
+
+        out.push_str("\nopcode classes:\n");
+        let mut classes: Vec<(usize, u64)> = self
+            .class_counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        classes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        for (class, count) in classes {
+            out.push_str(&format!(
+                "    {:X}xxx  {:>10}  {:>5.1}%\n",
+                class,
+                count,
+                percent(count, total)
+            ));
+        }
+
+        out.push_str("\nhot addresses:\n");
+        let labels = BTreeMap::new();
+        for (pc, count) in self.hot_addresses().into_iter().take(HOT_ADDRESSES) {
+            let start = usize::from(pc).min(memory.len());
+            let end = (start + 4).min(memory.len());
+            let asm = match disasm::decode_from(&memory[start..end], pc).first() {
+                Some(disasm::Line {
+                    instr: Some(instr),
+                    long,
+                    ..
+                }) => disasm::classic(instr, *long, &labels),
+                _ => String::new(),
+            };
+
+            out.push_str(&format!(
+                "    {:#06X}  {:>10}  {:>5.1}%  {}\n",
+                pc,
+                count,
+                percent(count, total),
+                asm
+            ));
+        }
+
+        out
+    }
+
+    /// One `kind,key,count` row per address, opcode class and frame.
+    pub fn csv(&self) -> String {
+        let mut out = String::from("kind,key,count\n");
+
+        let mut pcs: Vec<(&u16, &u64)> = self.pc_counts.iter().collect();
+        pcs.sort();
+        for (pc, count) in pcs {
+            out.push_str(&format!("pc,{:#06x},{}\n", pc, count));
+        }
+
+        for (class, count) in self.class_counts.iter().enumerate() {
+            out.push_str(&format!("class,{:x},{}\n", class, count));
+        }
+
+        for (frame, (instructions, draws)) in self.frames.iter().enumerate() {
+            out.push_str(&format!("frame_instructions,{},{}\n", frame, instructions));
+            out.push_str(&format!("frame_draws,{},{}\n", frame, draws));
+        }
+
+        out
+    }
+
+    pub fn write_csv(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.csv())
+    }
+
+    fn average(&self, value: impl Fn((u64, u64)) -> u64) -> f64 {
+        if self.frames.is_empty() {
+            return 0.0;
+        }
+
+        let sum: u64 = self.frames.iter().map(|f| value(*f)).sum();
+        sum as f64 / self.frames.len() as f64
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Profiler {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x200, 0x6111);
+        profiler.record(0x202, 0xd015);
+        profiler.record_draw();
+        profiler.end_frame();
+        profiler.record(0x202, 0xd015);
+        profiler.record_draw();
+        profiler.end_frame();
+
+        profiler
+    }
+
+    #[test]
+    fn counts_addresses_classes_and_frames() {
+        let profiler = sample();
+
+        assert_eq!(profiler.total(), 3);
+        assert_eq!(profiler.hot_addresses(), vec![(0x202, 2), (0x200, 1)]);
+        assert_eq!(profiler.class_counts[0xd], 2);
+        assert_eq!(profiler.frames, vec![(2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn report_shows_hot_instructions() {
+        let mut memory = vec![0; 0x200];
+        memory.extend([0x61, 0x11, 0xd0, 0x15]);
+
+        let report = sample().report(&memory);
+
+        assert!(report.contains("3 instructions over 2 frames"));
+        assert!(report.contains("instructions/frame: avg 1.5 max 2"));
+        assert!(report.contains("0x0202           2   66.7%  DRW V0, V1, 5"));
+    }
+
+    #[test]
+    fn csv_rows() {
+        let csv = sample().csv();
+
+        assert!(csv.starts_with("kind,key,count\npc,0x0200,1\npc,0x0202,2\n"));
+        assert!(csv.contains("class,d,2\n"));
+        assert!(csv.ends_with("frame_instructions,1,1\nframe_draws,1,1\n"));
+    }
+}
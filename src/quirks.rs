@@ -0,0 +1,167 @@
+/// Behaviour that differs between CHIP-8 interpreters. ROMs are written
+/// against one of them, so the profile has to match the ROM.
+///
+/// Profiles only pick the behaviour of the CHIP-8 instructions. The
+/// SUPER-CHIP and XO-CHIP instructions, like scrolling or the 128x64 screen,
+/// are not implemented and fault under every profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// FX55 and FX65 leave I pointing past the last register
+    pub memory_increment: bool,
+    /// DXYN waits for the next frame before the program continues
+    pub display_wait: bool,
+    /// Sprites are clipped at the screen edge instead of wrapping around
+    pub clipping: bool,
+    /// 8XY6 and 8XYE shift VX in place instead of shifting VY into VX
+    pub shift_vx: bool,
+    /// BNNN jumps to NNN plus VX, where X is the top nibble of NNN, instead of V0
+    pub jump_vx: bool,
+}
+
+/// Profile names accepted by `Quirks::from_name`
+pub const PROFILES: [&str; 3] = ["chip8", "superchip", "xochip"];
+
+/// Names of the single quirks, in field order
+pub const NAMES: [&str; 6] = [
+    "vf_reset",
+    "memory_increment",
+    "display_wait",
+    "clipping",
+    "shift_vx",
+    "jump_vx",
+];
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub fn chip8() -> Self {
+        Self {
+            vf_reset: true,
+            memory_increment: true,
+            display_wait: true,
+            clipping: true,
+            shift_vx: false,
+            jump_vx: false,
+        }
+    }
+
+    /// CHIP-48 and SUPER-CHIP on the HP 48
+    pub fn superchip() -> Self {
+        Self {
+            vf_reset: false,
+            memory_increment: false,
+            display_wait: false,
+            clipping: true,
+            shift_vx: true,
+            jump_vx: true,
+        }
+    }
+
+    /// Octo's XO-CHIP
+    pub fn xochip() -> Self {
+        Self {
+            vf_reset: false,
+            memory_increment: true,
+            display_wait: false,
+            clipping: false,
+            shift_vx: false,
+            jump_vx: false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Self::chip8()),
+            "superchip" => Some(Self::superchip()),
+            "xochip" => Some(Self::xochip()),
+            _ => None,
+        }
+    }
+
+    /// Name of the profile these quirks match, if any
+    pub fn profile(&self) -> Option<&'static str> {
+        PROFILES
+            .into_iter()
+            .find(|name| Self::from_name(name) == Some(*self))
+    }
+
+    fn flags(&self) -> [bool; 6] {
+        [
+            self.vf_reset,
+            self.memory_increment,
+            self.display_wait,
+            self.clipping,
+            self.shift_vx,
+            self.jump_vx,
+        ]
+    }
+
+    /// Names of the quirks that are on
+    pub fn enabled(&self) -> Vec<&'static str> {
+        NAMES
+            .iter()
+            .zip(self.flags())
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Turns on the named quirks and everything else off. `None` if a name
+    /// is unknown.
+    pub fn from_enabled<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut flags = [false; 6];
+        for name in names {
+            flags[NAMES.iter().position(|n| *n == name)?] = true;
+        }
+
+        let [vf_reset, memory_increment, display_wait, clipping, shift_vx, jump_vx] = flags;
+        Some(Self {
+            vf_reset,
+            memory_increment,
+            display_wait,
+            clipping,
+            shift_vx,
+            jump_vx,
+        })
+    }
+}
+
+/// The COSMAC VIP, the only instruction set the interpreter implements
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::chip8()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_profile_has_a_name() {
+        for name in PROFILES {
+            assert!(Quirks::from_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(Quirks::from_name("vip"), None);
+    }
+
+    #[test]
+    fn enabled_names_round_trip() {
+        for name in PROFILES {
+            let quirks = Quirks::from_name(name).unwrap();
+            assert_eq!(Quirks::from_enabled(quirks.enabled()), Some(quirks));
+        }
+        assert_eq!(Quirks::chip8().enabled(), NAMES[..4].to_vec());
+        assert_eq!(Quirks::from_enabled(["wrap"]), None);
+    }
+
+    #[test]
+    fn profile_names() {
+        for name in PROFILES {
+            assert_eq!(Quirks::from_name(name).unwrap().profile(), Some(name));
+        }
+        let custom = Quirks::from_enabled(["clipping"]).unwrap();
+        assert_eq!(custom.profile(), None);
+    }
+}
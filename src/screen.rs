@@ -0,0 +1,278 @@
+use serde::Deserialize;
+
+pub const WIDTH: usize = 64; // Pixels
+pub const HEIGHT: usize = 32; // Pixels
+
+/// The CHIP-8 framebuffer. Each pixel is 0 (off) or 1 (on).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// One line per row, `#` for pixels that are on and `.` otherwise
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+
+        for row in self.pixels.chunks(self.width) {
+            for pixel in row {
+                out.push(if *pixel != 0 { '#' } else { '.' });
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Plain PBM (P1) with one line per row, which image viewers open and
+    /// which still diffs well as text
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width, self.height);
+
+        for row in self.pixels.chunks(self.width) {
+            for pixel in row {
+                out.push(if *pixel != 0 { '1' } else { '0' });
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// 1 bit grayscale PNG at native resolution, white pixels on black
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width.div_ceil(8) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            for byte in row.chunks(8) {
+                let bits = byte
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, pixel)| **pixel != 0)
+                    .fold(0, |bits, (bit, _)| bits | 0x80 >> bit);
+                data.push(bits);
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+
+        let mut writer = encoder.write_header().expect("Error encoding PNG!");
+        writer.write_image_data(&data).expect("Error encoding PNG!");
+        writer.finish().expect("Error encoding PNG!");
+
+        out
+    }
+
+    /// XORs an 8 pixel wide sprite onto the screen. The start position wraps
+    /// around, pixels running off the edge are clipped if `clip` is set and
+    /// wrap around otherwise. Returns true if any pixel was turned off.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, rows: &[u8], clip: bool) -> bool {
+        let x = usize::from(x) % self.width;
+        let y = usize::from(y) % self.height;
+        let mut collision = false;
+
+        for (row, sprite) in rows.iter().enumerate() {
+            let mut py = y + row;
+            if py >= self.height {
+                if clip {
+                    break;
+                }
+                py %= self.height;
+            }
+
+            for bit in 0..8 {
+                let mut px = x + bit;
+                if px >= self.width {
+                    if clip {
+                        break;
+                    }
+                    px %= self.width;
+                }
+
+                if (0x80 >> bit) & sprite != 0 {
+                    let pixel = &mut self.pixels[py * self.width + px];
+                    collision |= *pixel != 0;
+                    *pixel ^= 1;
+                }
+            }
+        }
+
+        collision
+    }
+}
+
+/// Clockwise rotation of the screen, for games played on a vertical screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u32")]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    pub fn from_degrees(degrees: u32) -> Option<Self> {
+        match degrees {
+            0 => Some(Rotation::None),
+            90 => Some(Rotation::Cw90),
+            180 => Some(Rotation::Cw180),
+            270 => Some(Rotation::Cw270),
+            _ => None,
+        }
+    }
+
+    /// Width and height of a `width` x `height` screen once rotated
+    pub fn size(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Rotation::None | Rotation::Cw180 => (width, height),
+            Rotation::Cw90 | Rotation::Cw270 => (height, width),
+        }
+    }
+
+    /// Where pixel (`x`, `y`) of a `width` x `height` screen ends up once
+    /// rotated
+    pub fn apply(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Rotation::None => (x, y),
+            Rotation::Cw90 => (height - 1 - y, x),
+            Rotation::Cw180 => (width - 1 - x, height - 1 - y),
+            Rotation::Cw270 => (y, width - 1 - x),
+        }
+    }
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u32) -> Result<Self, Self::Error> {
+        Self::from_degrees(degrees)
+            .ok_or_else(|| format!("invalid rotation {}, expected 0, 90, 180 or 270", degrees))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn draw_sprite_xors_and_reports_collision() {
+        let mut screen = Screen::new();
+
+        assert!(!screen.draw_sprite(0, 0, &[0b1100_0000], true));
+        assert_eq!(screen.get(0, 0), 1);
+        assert_eq!(screen.get(1, 0), 1);
+
+        assert!(screen.draw_sprite(1, 0, &[0b1000_0000], true));
+        assert_eq!(screen.get(1, 0), 0);
+    }
+
+    #[test]
+    fn draw_sprite_wraps_start_and_clips_edges() {
+        let mut screen = Screen::new();
+
+        screen.draw_sprite(64 + 62, 31, &[0xff, 0xff], true);
+
+        assert_eq!(screen.get(62, 31), 1);
+        assert_eq!(screen.get(63, 31), 1);
+        assert_eq!(screen.get(0, 31), 0);
+        assert_eq!(screen.get(62, 0), 0);
+    }
+
+    #[test]
+    fn draw_sprite_wraps_edges_without_clipping() {
+        let mut screen = Screen::new();
+
+        screen.draw_sprite(62, 31, &[0xff, 0xff], false);
+
+        assert_eq!(screen.get(0, 31), 1);
+        assert_eq!(screen.get(62, 0), 1);
+        assert_eq!(screen.get(5, 0), 1);
+    }
+
+    #[test]
+    fn ascii_rendering() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(1, 0, &[0b1010_0000], true);
+
+        let ascii = screen.to_ascii();
+        let first = ascii.lines().next().unwrap();
+
+        assert_eq!(&first[..5], ".#.#.");
+        assert_eq!(ascii.lines().count(), HEIGHT);
+    }
+
+    #[test]
+    fn pbm_rendering() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 1, &[0b0110_0000], true);
+
+        let pbm = screen.to_pbm();
+        let lines: Vec<&str> = pbm.lines().collect();
+
+        assert_eq!(lines[0], "P1");
+        assert_eq!(lines[1], "64 32");
+        assert_eq!(&lines[3][..4], "0110");
+        assert_eq!(lines.len(), HEIGHT + 2);
+    }
+
+    #[test]
+    fn png_rendering() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 1, &[0b0110_0000], true);
+
+        let png = screen.to_png();
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+
+        assert_eq!((info.width, info.height), (64, 32));
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        assert_eq!(&data[8..10], &[0b0110_0000, 0]);
+    }
+
+    #[test]
+    fn rotations() {
+        assert_eq!(Rotation::from_degrees(90), Some(Rotation::Cw90));
+        assert_eq!(Rotation::from_degrees(45), None);
+        assert_eq!(Rotation::Cw90.size(64, 32), (32, 64));
+
+        // The top left pixel of a 64x32 screen
+        assert_eq!(Rotation::None.apply(0, 0, 64, 32), (0, 0));
+        assert_eq!(Rotation::Cw90.apply(0, 0, 64, 32), (31, 0));
+        assert_eq!(Rotation::Cw180.apply(0, 0, 64, 32), (63, 31));
+        assert_eq!(Rotation::Cw270.apply(0, 0, 64, 32), (0, 63));
+    }
+}
@@ -0,0 +1,132 @@
+//! PNG screenshots of the framebuffer in the display colors.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::palette::Palette;
+use crate::screen::Screen;
+
+/// RGB PNG of `screen` with every pixel drawn as a `scale` x `scale` square
+pub fn to_png(screen: &Screen, palette: &Palette, scale: u32) -> Vec<u8> {
+    let scale = scale.max(1);
+    let width = screen.width as u32 * scale;
+    let height = screen.height as u32 * scale;
+
+    encode_png(width, height, &to_rgb(screen, palette, scale))
+}
+
+/// PNG of a `width` x `height` RGB24 image
+pub fn encode_png(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().expect("Error encoding PNG!");
+    writer.write_image_data(data).expect("Error encoding PNG!");
+    writer.finish().expect("Error encoding PNG!");
+
+    out
+}
+
+/// Three bytes per pixel, rows top to bottom, each pixel `scale` times as
+/// wide and high
+pub fn to_rgb(screen: &Screen, palette: &Palette, scale: u32) -> Vec<u8> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (screen.width * scale, screen.height * scale);
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let on = screen.get(x / scale, y / scale) != 0;
+            data.extend(if on { palette.on() } else { palette.off() });
+        }
+    }
+
+    data
+}
+
+/// `chip8-YYYYMMDD-HHMMSS-mmm.png` in UTC, so screenshots sort by time
+pub fn file_name(time: SystemTime) -> String {
+    format!("{}.png", timestamp(time))
+}
+
+/// `chip8-YYYYMMDD-HHMMSS-mmm` in UTC, the stem of screenshot and capture
+/// file names
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    format!(
+        "chip8-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Writes `png` into `dir` under a timestamped name and returns the path
+pub fn save(dir: &Path, png: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(file_name(SystemTime::now()));
+    fs::write(&path, png)?;
+
+    Ok(path)
+}
+
+/// Gregorian date of a day count since 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn scaled_png_uses_the_palette() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(1, 0, &[0b1000_0000], true);
+        let palette = Palette::new("test", &[[1, 2, 3], [4, 5, 6]]).unwrap();
+
+        let png = to_png(&screen, &palette, 2);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+
+        assert_eq!((info.width, info.height), (128, 64));
+        assert_eq!(&data[..12], &[1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6]);
+        let second_row = 128 * 3;
+        assert_eq!(&data[second_row + 6..second_row + 9], &[4, 5, 6]);
+    }
+
+    #[test]
+    fn timestamped_file_names() {
+        assert_eq!(file_name(UNIX_EPOCH), "chip8-19700101-000000-000.png");
+
+        // 2024-02-29 13:45:07.250 UTC
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_214_307_250);
+        assert_eq!(file_name(time), "chip8-20240229-134507-250.png");
+    }
+}
@@ -0,0 +1,545 @@
+//! Input scripts for headless runs. Statements are separated by `;` or new
+//! lines, `#` starts a comment:
+//!
+//! ```text
+//! frame 60: press 5 for 3 frames
+//! wait until PC == 0x2F4 within 600 frames
+//! screenshot level1
+//! assert V3 == 7
+//! ```
+//!
+//! | statement                          | effect                                      |
+//! |------------------------------------|---------------------------------------------|
+//! | `frame N`                          | run until frame N starts                    |
+//! | `frame N: <statement>`             | the same, then the statement                |
+//! | `wait N frames`                    | run N more frames                           |
+//! | `wait until <cond> [within N frames]` | run instruction by instruction until the condition holds, 3600 frames at most |
+//! | `press K [for N frames]`           | hold key K (hex) for N frames, 1 by default |
+//! | `hold K` / `release K`             | hold key K until released                   |
+//! | `screenshot [name]`                | keep the current screen                     |
+//! | `assert <cond>`                    | fail unless the condition holds             |
+//!
+//! Conditions compare two operands with `==`, `!=`, `<`, `<=`, `>` or `>=`.
+//! Operands are numbers, `V0`-`VF`, `I`, `PC`, `DT`, `ST`, `SP` (stack
+//! depth), `FRAME` and memory bytes like `[0x300]`.
+
+use std::fmt;
+
+use crate::chip::{Chip8, FrameBudget};
+use crate::screen::Screen;
+use crate::trace;
+
+const DEFAULT_TIMEOUT: u64 = 3600; // Frames
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+type Result<T> = std::result::Result<T, ScriptError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Literal(u32),
+    Register(u8),
+    Index,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+    StackDepth,
+    Frame,
+    Memory(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+    /// Source text for error messages
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Frame(u64),
+    WaitFrames(u64),
+    WaitUntil { condition: Condition, timeout: u64 },
+    Press { key: u8, frames: u64 },
+    Hold(u8),
+    Release(u8),
+    Screenshot(Option<String>),
+    Assert(Condition),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub line: usize,
+    pub command: Command,
+}
+
+pub fn parse(source: &str) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let code = line.split('#').next().unwrap_or("");
+
+        for text in code.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let error = |message: String| ScriptError {
+                line: line_number,
+                message,
+            };
+
+            let mut text = text;
+            if let Some((frame, rest)) = text.split_once(':') {
+                let frame = frame.trim().strip_prefix("frame").ok_or_else(|| {
+                    error(format!("expected 'frame N:' before '{}'", rest.trim()))
+                })?;
+                let frame = parse_number(frame).map_err(error)?;
+                statements.push(Statement {
+                    line: line_number,
+                    command: Command::Frame(frame),
+                });
+                text = rest.trim();
+            }
+
+            statements.push(Statement {
+                line: line_number,
+                command: parse_command(text).map_err(error)?,
+            });
+        }
+    }
+
+    Ok(statements)
+}
+
+fn parse_command(text: &str) -> std::result::Result<Command, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["frame", n] => Ok(Command::Frame(parse_number(n)?)),
+        ["wait", n, "frames" | "frame"] => Ok(Command::WaitFrames(parse_number(n)?)),
+        ["wait", "until", ..] => {
+            let rest = text["wait".len()..].trim_start()["until".len()..].trim();
+            let (condition, timeout) = match rest.rsplit_once("within") {
+                Some((condition, limit)) => {
+                    let limit = limit.trim();
+                    let limit = limit
+                        .strip_suffix("frames")
+                        .or_else(|| limit.strip_suffix("frame"))
+                        .ok_or_else(|| format!("expected 'within N frames', got '{}'", limit))?;
+                    (condition, parse_number(limit)?)
+                }
+                None => (rest, DEFAULT_TIMEOUT),
+            };
+            Ok(Command::WaitUntil {
+                condition: parse_condition(condition)?,
+                timeout,
+            })
+        }
+        ["press", key] => Ok(Command::Press {
+            key: parse_key(key)?,
+            frames: 1,
+        }),
+        ["press", key, "for", n, "frames" | "frame"] => Ok(Command::Press {
+            key: parse_key(key)?,
+            frames: parse_number(n)?,
+        }),
+        ["hold", key] => Ok(Command::Hold(parse_key(key)?)),
+        ["release", key] => Ok(Command::Release(parse_key(key)?)),
+        ["screenshot"] => Ok(Command::Screenshot(None)),
+        ["screenshot", name] => Ok(Command::Screenshot(Some(name.to_string()))),
+        ["assert", ..] => Ok(Command::Assert(parse_condition(
+            text["assert".len()..].trim(),
+        )?)),
+        _ => Err(format!("unknown statement '{}'", text)),
+    }
+}
+
+fn parse_condition(text: &str) -> std::result::Result<Condition, String> {
+    const COMPARISONS: [(&str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    let text = text.trim();
+    for (symbol, comparison) in COMPARISONS {
+        if let Some((left, right)) = text.split_once(symbol) {
+            return Ok(Condition {
+                left: parse_operand(left)?,
+                comparison,
+                right: parse_operand(right)?,
+                text: text.to_string(),
+            });
+        }
+    }
+
+    Err(format!("expected a comparison, got '{}'", text))
+}
+
+fn parse_operand(text: &str) -> std::result::Result<Operand, String> {
+    let text = text.trim();
+    let upper = text.to_ascii_uppercase();
+
+    let operand = match upper.as_str() {
+        "I" => Operand::Index,
+        "PC" => Operand::Pc,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "SP" => Operand::StackDepth,
+        "FRAME" => Operand::Frame,
+        _ if upper.len() == 2 && upper.starts_with('V') => Operand::Register(
+            parse_key(&upper[1..]).map_err(|_| format!("unknown register '{}'", text))?,
+        ),
+        _ if text.starts_with('[') && text.ends_with(']') => {
+            let addr = parse_number(&text[1..text.len() - 1])?;
+            Operand::Memory(
+                u16::try_from(addr).map_err(|_| format!("address '{}' out of range", text))?,
+            )
+        }
+        _ => Operand::Literal(
+            u32::try_from(parse_number(text)?)
+                .map_err(|_| format!("number '{}' out of range", text))?,
+        ),
+    };
+
+    Ok(operand)
+}
+
+fn parse_number(text: &str) -> std::result::Result<u64, String> {
+    trace::parse_int(text).ok_or_else(|| format!("invalid number '{}'", text.trim()))
+}
+
+fn parse_key(text: &str) -> std::result::Result<u8, String> {
+    u8::from_str_radix(text, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid key '{}'", text))
+}
+
+impl Operand {
+    fn value(&self, chip: &Chip8) -> u32 {
+        match *self {
+            Operand::Literal(n) => n,
+            Operand::Register(x) => u32::from(chip.registers()[usize::from(x)]),
+            Operand::Index => u32::from(chip.ireg()),
+            Operand::Pc => u32::from(chip.pc()),
+            Operand::DelayTimer => u32::from(chip.delay_timer),
+            Operand::SoundTimer => u32::from(chip.sound_timer),
+            Operand::StackDepth => chip.stack.len() as u32,
+            Operand::Frame => chip.frame as u32,
+            Operand::Memory(addr) => {
+                u32::from(chip.memory().get(usize::from(addr)).copied().unwrap_or(0))
+            }
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, chip: &Chip8) -> bool {
+        let (left, right) = (self.left.value(chip), self.right.value(chip));
+
+        match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+
+    /// The condition with the current operand values, e.g. `V3 == 7 (V3 is 5)`
+    fn explain(&self, chip: &Chip8) -> String {
+        let mut values = Vec::new();
+        for operand in [self.left, self.right] {
+            if !matches!(operand, Operand::Literal(_)) {
+                values.push(format!("{} is {:#x}", operand, operand.value(chip)));
+            }
+        }
+
+        if values.is_empty() {
+            self.text.clone()
+        } else {
+            format!("{} ({})", self.text, values.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Literal(n) => write!(f, "{:#x}", n),
+            Operand::Register(x) => write!(f, "V{:X}", x),
+            Operand::Index => write!(f, "I"),
+            Operand::Pc => write!(f, "PC"),
+            Operand::DelayTimer => write!(f, "DT"),
+            Operand::SoundTimer => write!(f, "ST"),
+            Operand::StackDepth => write!(f, "SP"),
+            Operand::Frame => write!(f, "FRAME"),
+            Operand::Memory(addr) => write!(f, "[{:#x}]", addr),
+        }
+    }
+}
+
+/// Runs a script against a machine, frame by frame. Waits for a condition
+/// stop at the instruction that makes it true.
+pub struct Runner<'a> {
+    chip: &'a mut Chip8,
+    budget: FrameBudget,
+    /// Instructions left in the current frame, `None` before it started
+    frame_left: Option<u32>,
+    /// Frame before which each key is released
+    held_until: [u64; 16],
+    pub screenshots: Vec<(String, Screen)>,
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(chip: &'a mut Chip8, instr_per_secs: f32) -> Self {
+        Self {
+            chip,
+            budget: FrameBudget::new(instr_per_secs),
+            frame_left: None,
+            held_until: [0; 16],
+            screenshots: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, script: &[Statement]) -> Result<()> {
+        for statement in script {
+            let error = |message: String| ScriptError {
+                line: statement.line,
+                message,
+            };
+
+            match &statement.command {
+                Command::Frame(frame) => {
+                    if self.chip.frame > *frame {
+                        return Err(error(format!(
+                            "frame {} has already passed, this is frame {}",
+                            frame, self.chip.frame
+                        )));
+                    }
+                    while self.chip.frame < *frame {
+                        self.finish_frame();
+                    }
+                }
+                Command::WaitFrames(frames) => {
+                    for _ in 0..*frames {
+                        self.finish_frame();
+                    }
+                }
+                Command::WaitUntil { condition, timeout } => {
+                    let deadline = self.chip.frame.saturating_add(*timeout);
+                    while !condition.holds(self.chip) {
+                        if self.chip.frame >= deadline {
+                            return Err(error(format!(
+                                "timed out after {} frames waiting for {}",
+                                timeout,
+                                condition.explain(self.chip)
+                            )));
+                        }
+                        self.run_frame_until(|chip| condition.holds(chip));
+                    }
+                }
+                Command::Press { key, frames } => {
+                    self.held_until[usize::from(*key)] = self.chip.frame.saturating_add(*frames);
+                    self.chip.key_pad[usize::from(*key)] = true;
+                }
+                Command::Hold(key) => {
+                    self.held_until[usize::from(*key)] = u64::MAX;
+                    self.chip.key_pad[usize::from(*key)] = true;
+                }
+                Command::Release(key) => {
+                    self.held_until[usize::from(*key)] = 0;
+                    self.chip.key_pad[usize::from(*key)] = false;
+                }
+                Command::Screenshot(name) => {
+                    let name = name
+                        .clone()
+                        .unwrap_or_else(|| format!("screenshot-{}", self.screenshots.len() + 1));
+                    self.screenshots.push((name, self.chip.screen.clone()));
+                }
+                Command::Assert(condition) => {
+                    if !condition.holds(self.chip) {
+                        return Err(error(format!(
+                            "assertion failed: {}",
+                            condition.explain(self.chip)
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the rest of the current frame, starting a new one if needed, or
+    /// stops early once `stop` holds
+    fn run_frame_until(&mut self, stop: impl FnMut(&Chip8) -> bool) {
+        let left = match self.frame_left {
+            Some(left) => left,
+            None => self.start_frame(),
+        };
+
+        let (executed, ended) = self.chip.run_frame_until(left, stop);
+        self.frame_left = (!ended).then(|| left - executed);
+    }
+
+    fn finish_frame(&mut self) {
+        self.run_frame_until(|_| false);
+    }
+
+    fn start_frame(&mut self) -> u32 {
+        let frame = self.chip.frame;
+        for (down, until) in self.chip.key_pad.iter_mut().zip(self.held_until) {
+            *down = frame < until;
+        }
+
+        let instructions = self.budget.take_frame();
+        self.frame_left = Some(instructions);
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn parses_statements() {
+        let script = parse(
+            "frame 60: press 5 for 3 frames; wait until PC==0x2F4\n\
+             # comment\n\
+             screenshot; assert V3 == 7 # trailing\n\
+             wait until [0x300] >= 2 within 10 frames",
+        )
+        .unwrap();
+        let commands: Vec<&Command> = script.iter().map(|s| &s.command).collect();
+
+        assert_eq!(commands[0], &Command::Frame(60));
+        assert_eq!(commands[1], &Command::Press { key: 5, frames: 3 });
+        assert!(matches!(
+            commands[2],
+            Command::WaitUntil {
+                condition: Condition {
+                    left: Operand::Pc,
+                    comparison: Comparison::Eq,
+                    right: Operand::Literal(0x2f4),
+                    ..
+                },
+                timeout: DEFAULT_TIMEOUT
+            }
+        ));
+        assert_eq!(commands[3], &Command::Screenshot(None));
+        assert_eq!(script[4].line, 3);
+        assert!(matches!(
+            commands[5],
+            Command::WaitUntil {
+                condition: Condition {
+                    left: Operand::Memory(0x300),
+                    comparison: Comparison::Ge,
+                    ..
+                },
+                timeout: 10
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_errors_carry_line_numbers() {
+        let error = |source: &str| parse(source).unwrap_err().to_string();
+
+        assert_eq!(error("\npress G"), "line 2: invalid key 'G'");
+        assert_eq!(
+            error("assert V3"),
+            "line 1: expected a comparison, got 'V3'"
+        );
+        assert_eq!(error("jump 3"), "line 1: unknown statement 'jump 3'");
+        assert_eq!(
+            error("at 5: press 1"),
+            "line 1: expected 'frame N:' before 'press 1'"
+        );
+    }
+
+    #[test]
+    fn drives_keys_and_checks_state() {
+        // loop: skip unless key 5 is down, else V3 += 1
+        let rom = [0xe5, 0xa1, 0x73, 0x01, 0x12, 0x00];
+        let mut chip = Chip8::from_rom(&rom, Quirks::default());
+        chip.registers[5] = 5;
+        let script = parse(
+            "frame 2: press 5 for 2 frames\n\
+             wait 4 frames\n\
+             assert V3 == 2; assert FRAME == 6; screenshot end\n\
+             wait until V3 == 3 within 5 frames",
+        )
+        .unwrap();
+
+        // One loop iteration per frame
+        let mut runner = Runner::new(&mut chip, 180.0);
+        let result = runner.run(&script);
+
+        assert_eq!(runner.screenshots.len(), 1);
+        assert_eq!(runner.screenshots[0].0, "end");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "line 4: timed out after 5 frames waiting for V3 == 3 (V3 is 0x2)"
+        );
+    }
+
+    #[test]
+    fn huge_frame_counts_do_not_overflow() {
+        // V0 += 1 until V0 == 3, then jump to itself
+        let rom = [0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x12, 0x06];
+        let mut chip = Chip8::from_rom(&rom, Quirks::default());
+        chip.frame = 10;
+        let script = parse(
+            "press 1 for 18446744073709551615 frames
+             wait until V0 == 3 within 18446744073709551615 frames
+             assert PC == 0x202",
+        )
+        .unwrap();
+
+        Runner::new(&mut chip, 60.0).run(&script).unwrap();
+        assert!(chip.key_pad[1]);
+    }
+
+    #[test]
+    fn failed_assertions_show_values() {
+        let mut chip = Chip8::from_rom(&[0x12, 0x00], Quirks::default());
+        let script = parse("wait 1 frame; assert PC != 0x200").unwrap();
+
+        let result = Runner::new(&mut chip, 60.0).run(&script);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "line 1: assertion failed: PC != 0x200 (PC is 0x200)"
+        );
+    }
+}
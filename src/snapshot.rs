@@ -0,0 +1,356 @@
+//! Golden screenshot testing. Runs a ROM headlessly, captures the screen at
+//! chosen frames and compares it with a snapshot stored as ASCII or plain PBM.
+//!
+//! ```no_run
+//! use cpu_emulator::chip::Chip8;
+//! use cpu_emulator::quirks::Quirks;
+//! use cpu_emulator::snapshot::{self, Press, Snapshots};
+//!
+//! let rom = std::fs::read("game.ch8").unwrap();
+//! let mut chip = Chip8::from_rom(&rom, Quirks::chip8());
+//! let start = Press { frame: 10, key: 5, frames: 3 };
+//!
+//! let snapshots = Snapshots::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"));
+//! let screens = snapshot::capture(&mut chip, 1000, &[30, 120], &[start]);
+//! snapshots.assert("title", &screens[0]);
+//! snapshots.assert("level1", &screens[1]);
+//! ```
+//!
+//! Run the tests with `UPDATE_SNAPSHOTS=1` to write the current screens
+//! instead of comparing them.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::chip::Chip8;
+use crate::screen::Screen;
+
+/// Setting this to anything but empty or `0` updates the stored snapshots
+pub const UPDATE_VAR: &str = "UPDATE_SNAPSHOTS";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `#` and `.` per pixel, stored as `.txt`
+    Ascii,
+    /// Plain PBM, stored as `.pbm`
+    Pbm,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Ascii => "txt",
+            Format::Pbm => "pbm",
+        }
+    }
+
+    pub fn render(self, screen: &Screen) -> String {
+        match self {
+            Format::Ascii => screen.to_ascii(),
+            Format::Pbm => screen.to_pbm(),
+        }
+    }
+}
+
+/// Holds `key` down from `frame` on for `frames` frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Press {
+    pub frame: u64,
+    pub key: usize,
+    pub frames: u64,
+}
+
+impl Press {
+    pub fn holds(&self, frame: u64) -> bool {
+        (self.frame..self.frame + self.frames).contains(&frame)
+    }
+}
+
+/// Runs frames until `chip.frame` reaches `frame`, setting the key pad from
+/// `presses` before each one.
+pub fn run_until(chip: &mut Chip8, frame: u64, instructions_per_frame: u32, presses: &[Press]) {
+    while chip.frame < frame {
+        chip.key_pad = [false; 16];
+        for press in presses.iter().filter(|p| p.holds(chip.frame)) {
+            chip.key_pad[press.key & 0xf] = true;
+        }
+
+        chip.run_frame(instructions_per_frame);
+    }
+}
+
+/// The screen once each of the frames in `at` has run. `at` should be in
+/// ascending order, the chip only runs forward.
+pub fn capture(
+    chip: &mut Chip8,
+    instructions_per_frame: u32,
+    at: &[u64],
+    presses: &[Press],
+) -> Vec<Screen> {
+    at.iter()
+        .map(|frame| {
+            run_until(chip, *frame, instructions_per_frame, presses);
+            chip.screen.clone()
+        })
+        .collect()
+}
+
+/// A directory of stored snapshots
+pub struct Snapshots {
+    dir: PathBuf,
+    format: Format,
+    update: bool,
+}
+
+impl Snapshots {
+    /// ASCII snapshots in `dir`, updated instead of compared when
+    /// `UPDATE_SNAPSHOTS` is set.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let update = env::var(UPDATE_VAR).is_ok_and(|v| !v.is_empty() && v != "0");
+
+        Self {
+            dir: dir.into(),
+            format: Format::Ascii,
+            update,
+        }
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn update(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}", name, self.format.extension()))
+    }
+
+    /// Compares `screen` with the snapshot called `name`, or stores it when
+    /// updating.
+    pub fn check(&self, name: &str, screen: &Screen) -> Result<(), Mismatch> {
+        let path = self.path(name);
+        let actual = self.format.render(screen);
+
+        if self.update {
+            fs::create_dir_all(&self.dir).expect("Error creating snapshot directory!");
+            fs::write(&path, &actual).expect("Error writing snapshot!");
+            return Ok(());
+        }
+
+        match fs::read_to_string(&path) {
+            Ok(expected) if expected == actual => Ok(()),
+            Ok(expected) => Err(Mismatch {
+                path,
+                expected: Some(expected),
+                actual,
+            }),
+            Err(_) => Err(Mismatch {
+                path,
+                expected: None,
+                actual,
+            }),
+        }
+    }
+
+    /// Like `check`, but panics with a side-by-side diff on a mismatch.
+    #[track_caller]
+    pub fn assert(&self, name: &str, screen: &Screen) {
+        if let Err(mismatch) = self.check(name, screen) {
+            panic!("{}", mismatch);
+        }
+    }
+}
+
+/// A screen that did not match its snapshot. `expected` is `None` when the
+/// snapshot does not exist yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+impl Mismatch {
+    /// Pixels that differ, counting pixels outside the smaller of both images
+    pub fn pixel_count(&self) -> usize {
+        let expected = parse(self.expected.as_deref().unwrap_or(""));
+        let actual = parse(&self.actual);
+        let (width, height) = bounds(&expected, &actual);
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|(x, y)| pixel(&expected, *x, *y) != pixel(&actual, *x, *y))
+            .count()
+    }
+
+    /// Expected, actual and a diff next to each other. The diff shows `+`
+    /// for pixels that are only on in the actual screen, `-` for pixels that
+    /// are only on in the snapshot and `?` where the sizes differ.
+    pub fn side_by_side(&self) -> String {
+        let expected = parse(self.expected.as_deref().unwrap_or(""));
+        let actual = parse(&self.actual);
+        let (width, height) = bounds(&expected, &actual);
+
+        let cell = |on: Option<bool>| match on {
+            Some(true) => '#',
+            Some(false) => '.',
+            None => ' ',
+        };
+
+        let mut out = format!("{:<w$}  {:<w$}  diff\n", "expected", "actual", w = width);
+        for y in 0..height {
+            let mut line = String::new();
+            line.extend((0..width).map(|x| cell(pixel(&expected, x, y))));
+            line.push_str("  ");
+            line.extend((0..width).map(|x| cell(pixel(&actual, x, y))));
+            line.push_str("  ");
+            line.extend(
+                (0..width).map(|x| match (pixel(&expected, x, y), pixel(&actual, x, y)) {
+                    (a, b) if a == b => cell(a),
+                    (Some(true), _) => '-',
+                    (_, Some(true)) => '+',
+                    _ => '?',
+                }),
+            );
+
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.expected.is_none() {
+            return write!(
+                f,
+                "snapshot {} does not exist, run with {}=1 to create it\n{}",
+                self.path.display(),
+                UPDATE_VAR,
+                self.actual
+            );
+        }
+
+        write!(
+            f,
+            "snapshot {} does not match, {} pixels differ ({}=1 updates it)\n{}",
+            self.path.display(),
+            self.pixel_count(),
+            UPDATE_VAR,
+            self.side_by_side()
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Reads a stored snapshot in either format back into rows of pixels
+fn parse(text: &str) -> Vec<Vec<bool>> {
+    match text.strip_prefix("P1") {
+        Some(body) => parse_pbm(body),
+        None => parse_ascii(text),
+    }
+}
+
+/// Rows of `#` (on) and `.` (off), as stored by `Format::Ascii` and written
+/// by hand in tests
+pub(crate) fn parse_ascii(text: &str) -> Vec<Vec<bool>> {
+    text.lines()
+        .map(|line| line.chars().map(|c| c == '#').collect())
+        .collect()
+}
+
+fn parse_pbm(body: &str) -> Vec<Vec<bool>> {
+    let body: Vec<&str> = body
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .collect();
+    let body = body.join("\n");
+    let mut tokens = body.split_whitespace();
+
+    let width: usize = tokens.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+    let _height = tokens.next();
+    let bits: Vec<bool> = tokens.flat_map(str::chars).map(|c| c == '1').collect();
+
+    bits.chunks(width.max(1)).map(<[bool]>::to_vec).collect()
+}
+
+fn bounds(a: &[Vec<bool>], b: &[Vec<bool>]) -> (usize, usize) {
+    let width = a.iter().chain(b).map(Vec::len).max().unwrap_or(0);
+    (width, a.len().max(b.len()))
+}
+
+fn pixel(rows: &[Vec<bool>], x: usize, y: usize) -> Option<bool> {
+    rows.get(y).and_then(|row| row.get(x)).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("snapshot-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn sample() -> Screen {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0b1100_0000], true);
+        screen
+    }
+
+    #[test]
+    fn update_then_check() {
+        let dir = temp_dir("update");
+        let snapshots = Snapshots::new(&dir).format(Format::Pbm).update(false);
+
+        assert_eq!(
+            snapshots.check("screen", &sample()).unwrap_err().expected,
+            None
+        );
+
+        snapshots.update(true).check("screen", &sample()).unwrap();
+        let snapshots = Snapshots::new(&dir).format(Format::Pbm).update(false);
+        assert!(snapshots.path("screen").ends_with("screen.pbm"));
+        assert_eq!(snapshots.check("screen", &sample()), Ok(()));
+        assert!(snapshots.check("screen", &Screen::new()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn side_by_side_diff() {
+        let mismatch = Mismatch {
+            path: PathBuf::from("x.txt"),
+            expected: Some("##.\n...\n".to_string()),
+            actual: "#.#\n..\n".to_string(),
+        };
+
+        assert_eq!(mismatch.pixel_count(), 3);
+        assert_eq!(
+            mismatch.side_by_side(),
+            "expected  actual  diff\n##.  #.#  #-+\n...  ..   ..?\n"
+        );
+    }
+
+    #[test]
+    fn pbm_and_ascii_parse_alike() {
+        let screen = sample();
+
+        assert_eq!(parse(&screen.to_pbm()), parse(&screen.to_ascii()));
+        assert_eq!(
+            parse("P1 # comment\n3 1\n1 0\n1\n"),
+            vec![vec![true, false, true]]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// Address to name mapping, as written by the assembler and read by the
+/// disassembler and profilers.
+pub type SymbolMap = BTreeMap<u16, String>;
+
+/// Formats labels as one `0x0202 name` line per label, sorted by address.
+pub fn format<'a>(labels: impl IntoIterator<Item = (&'a String, &'a u16)>) -> String {
+    let mut entries: Vec<(u16, &String)> = labels
+        .into_iter()
+        .map(|(name, addr)| (*addr, name))
+        .collect();
+    entries.sort();
+
+    let mut out = String::new();
+    for (addr, name) in entries {
+        out.push_str(&format!("{:#06x} {}\n", addr, name));
+    }
+
+    out
+}
+
+/// Parses a symbol map. Blank lines, `#` comments and malformed lines are
+/// skipped; the first name given for an address wins.
+pub fn parse(text: &str) -> SymbolMap {
+    let mut symbols = SymbolMap::new();
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut parts = line.split_whitespace();
+
+        let (Some(addr), Some(name)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+
+        let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            symbols.entry(addr).or_insert_with(|| name.to_string());
+        }
+    }
+
+    symbols
+}
+
+pub fn read(path: &str) -> io::Result<SymbolMap> {
+    Ok(parse(&fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_and_parse() {
+        let mut labels = BTreeMap::new();
+        labels.insert("main".to_string(), 0x200);
+        labels.insert("draw".to_string(), 0x2a4);
+        labels.insert("start".to_string(), 0x200);
+
+        let text = format(&labels);
+        assert_eq!(text, "0x0200 main\n0x0200 start\n0x02a4 draw\n");
+
+        let symbols = parse(&text);
+        assert_eq!(symbols.get(&0x200).unwrap(), "main");
+        assert_eq!(symbols.get(&0x2a4).unwrap(), "draw");
+    }
+
+    #[test]
+    fn parse_skips_junk() {
+        let symbols = parse("# header\n\nnonsense\n0x0300 sprite # trailing\n");
+
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols.get(&0x300).unwrap(), "sprite");
+    }
+}
@@ -0,0 +1,392 @@
+//! Runs a ROM in a terminal, for machines where SDL cannot open a window, e.g.
+//! over SSH. The framebuffer is drawn with Unicode block or braille characters
+//! in the palette's colors and keys are read from stdin in raw mode.
+
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+
+use crate::capture;
+use crate::chip::{Chip8, FrameBudget, RunOptions};
+use crate::headless;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+
+/// Frames a key stays down after the terminal last reported it, for
+/// terminals that only report presses. Long enough to bridge the gaps between
+/// the terminal's key repeats.
+const HOLD_FRAMES: u64 = 10;
+
+/// Shades from this brightness on are lit for the characters with only one
+/// color
+const LIT_SHADE: u8 = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Glyphs {
+    /// `▀` with the top pixel as foreground and the bottom one as background,
+    /// one character per 1x2 pixels
+    HalfBlocks,
+    /// Quadrant blocks, one character per 2x2 pixels
+    QuarterBlocks,
+    /// Braille dots, one character per 2x4 pixels
+    Braille,
+}
+
+impl Glyphs {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "half" => Some(Glyphs::HalfBlocks),
+            "quarter" => Some(Glyphs::QuarterBlocks),
+            "braille" => Some(Glyphs::Braille),
+            _ => None,
+        }
+    }
+
+    /// Pixels per character, across and down
+    pub fn cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlocks => (1, 2),
+            Glyphs::QuarterBlocks => (2, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+}
+
+/// Quadrant characters by lit pixels: 1 top left, 2 top right, 4 bottom left
+/// and 8 bottom right
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// Bit of each braille dot, by row and column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// The framebuffer as lines of colored characters, from its `shades` (see
+/// `Phosphor::update`). Lines end with `\r\n` as the terminal is in raw mode.
+pub fn render(
+    shades: &[u8],
+    width: usize,
+    height: usize,
+    palette: &Palette,
+    glyphs: Glyphs,
+) -> String {
+    let shade = |x: usize, y: usize| match x < width && y < height {
+        true => shades[y * width + x],
+        false => 0,
+    };
+    let lit = |x: usize, y: usize| shade(x, y) >= LIT_SHADE;
+    let (cell_width, cell_height) = glyphs.cell();
+
+    let mut out = String::new();
+    for row in (0..height).step_by(cell_height) {
+        let mut colors = None;
+        for column in (0..width).step_by(cell_width) {
+            let (c, foreground, background) = match glyphs {
+                Glyphs::HalfBlocks => (
+                    '▀',
+                    palette.shade(shade(column, row)),
+                    palette.shade(shade(column, row + 1)),
+                ),
+                Glyphs::QuarterBlocks => {
+                    let bits = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (dx, dy))| lit(column + dx, row + dy))
+                        .fold(0, |bits, (bit, _)| bits | 1 << bit);
+                    (QUADRANTS[bits], palette.on(), palette.off())
+                }
+                Glyphs::Braille => {
+                    let mut bits = 0;
+                    for (dy, dots) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dx, dot) in dots.iter().enumerate() {
+                            if lit(column + dx, row + dy) {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    let c = char::from_u32(0x2800 + bits).unwrap_or(' ');
+                    (c, palette.on(), palette.off())
+                }
+            };
+
+            // Colors only change between differently shaded cells
+            if colors != Some((foreground, background)) {
+                let ([fr, fg, fb], [br, bg, bb]) = (foreground, background);
+                let _ = write!(out, "\x1b[38;2;{fr};{fg};{fb};48;2;{br};{bg};{bb}m");
+                colors = Some((foreground, background));
+            }
+            out.push(c);
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+
+    out
+}
+
+/// The key pad key for a typed character, laid out like the keyboard in the
+/// window
+pub fn key_index(c: char) -> Option<usize> {
+    c.to_digit(16).map(|key| key as usize)
+}
+
+/// Key pad state from key events. Terminals that report releases hold a key
+/// until it is released, others for `HOLD_FRAMES` after each press or repeat.
+#[derive(Debug, Default)]
+pub struct Keys {
+    /// Frame each held key was last pressed on
+    pressed: [Option<u64>; 16],
+    releases: bool,
+}
+
+impl Keys {
+    pub fn new(releases: bool) -> Self {
+        Self {
+            releases,
+            ..Default::default()
+        }
+    }
+
+    pub fn press(&mut self, key: usize, frame: u64) {
+        self.pressed[key] = Some(frame);
+    }
+
+    pub fn release(&mut self, key: usize) {
+        self.pressed[key] = None;
+    }
+
+    pub fn key_pad(&self, frame: u64) -> [bool; 16] {
+        self.pressed.map(|pressed| match pressed {
+            Some(_) if self.releases => true,
+            Some(at) => frame < at + HOLD_FRAMES,
+            None => false,
+        })
+    }
+}
+
+/// Raw mode on the alternate screen, restored when dropped, also on a panic
+struct Session {
+    /// The terminal reports key releases
+    releases: bool,
+}
+
+impl Session {
+    fn start() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Self { releases })
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.releases {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs `rom` in the terminal until Esc or Ctrl+C
+pub fn run(rom: &[u8], mut options: RunOptions, glyphs: Glyphs) -> io::Result<Chip8> {
+    let (mut chip, instr_per_secs) = headless::prepare(rom, &mut options);
+    let mut phosphor = Phosphor::new(options.persistence);
+    let mut capture = capture::start(options.gif.as_deref(), false, options.palette.clone())?;
+
+    let session = Session::start()?;
+    let mut keys = Keys::new(session.releases);
+    let mut out = BufWriter::new(io::stdout());
+    let mut shown = String::new();
+    let frame_time = Duration::from_secs_f32(1.0 / 60.0);
+    let mut budget = FrameBudget::new(instr_per_secs);
+    // Shown in the status line, stderr would write over the screen
+    let mut message = "Esc quits";
+
+    'running: loop {
+        let frame_start = Instant::now();
+
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => break 'running,
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers,
+                    ..
+                }) if modifiers.contains(KeyModifiers::CONTROL) => break 'running,
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c),
+                    kind,
+                    ..
+                }) => {
+                    if let Some(key) = key_index(c) {
+                        match kind {
+                            KeyEventKind::Release => keys.release(key),
+                            _ => keys.press(key, chip.frame),
+                        }
+                    }
+                }
+                // Everything is drawn again after a resize
+                Event::Resize(..) => shown.clear(),
+                _ => {}
+            }
+        }
+
+        let frame = chip.frame as usize;
+        chip.key_pad = options
+            .replay
+            .as_ref()
+            .and_then(|movie| movie.key_pad(frame))
+            .unwrap_or_else(|| keys.key_pad(chip.frame));
+        if let Some(movie) = chip.recording.as_mut() {
+            movie.record(&chip.key_pad);
+        }
+
+        let was_beeping = chip.sound_timer > 0;
+        chip.run_frame(budget.take_frame());
+        if let Some(capture) = capture.as_mut() {
+            capture.frame(&chip.screen, chip.sound_timer > 0)?;
+        }
+
+        if let Some(movie) = options.replay.as_ref() {
+            if movie.frames.len() == frame + 1 {
+                message = if movie.matches_end_state(&chip) {
+                    "Replay finished in the recorded state"
+                } else {
+                    "Replay finished, but the state differs from the recording"
+                };
+            }
+        }
+
+        let shades = phosphor.update(&chip.screen);
+        let mut screen = format!("{}", cursor::MoveTo(0, 0));
+        screen += &render(
+            &shades,
+            chip.screen.width,
+            chip.screen.height,
+            &options.palette,
+            glyphs,
+        );
+        match chip.fault {
+            Some(fault) => screen += &format!("{}\x1b[K", fault),
+            None => screen += &format!("{}\x1b[K", message),
+        }
+        if screen != shown {
+            out.write_all(screen.as_bytes())?;
+            shown = screen;
+        }
+        // The terminal bell stands in for the buzzer
+        if !was_beeping && chip.sound_timer > 0 {
+            out.write_all(b"\x07")?;
+        }
+        out.flush()?;
+
+        thread::sleep(frame_time.saturating_sub(frame_start.elapsed()));
+    }
+
+    drop(session);
+    if let Some(capture) = capture {
+        capture.finish()?;
+    }
+
+    Ok(chip)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::snapshot;
+
+    /// Characters of `render` without the color escapes
+    fn characters(rendered: &str) -> String {
+        let mut out = String::new();
+        let mut escape = false;
+        for c in rendered.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                '\r' => {}
+                _ if !escape => out.push(c),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// Shades from rows of `#` and `.`
+    fn shades(rows: &[&str]) -> Vec<u8> {
+        snapshot::parse_ascii(&rows.join("\n"))
+            .concat()
+            .into_iter()
+            .map(|on| if on { 255 } else { 0 })
+            .collect()
+    }
+
+    #[test]
+    fn half_blocks_color_both_halves() {
+        let palette = Palette::new("bw", &[[0, 0, 0], [255, 255, 255]]).unwrap();
+        let rendered = render(&shades(&["#.", ".#"]), 2, 2, &palette, Glyphs::HalfBlocks);
+
+        assert_eq!(characters(&rendered), "▀▀\n");
+        assert!(rendered.starts_with("\x1b[38;2;255;255;255;48;2;0;0;0m▀"));
+        assert!(rendered.contains("\x1b[38;2;0;0;0;48;2;255;255;255m▀"));
+    }
+
+    #[test]
+    fn quarter_blocks_and_braille() {
+        let palette = Palette::default();
+        let rows = ["#..#", ".###", "....", "#..."];
+
+        let quarter = render(&shades(&rows), 4, 4, &palette, Glyphs::QuarterBlocks);
+        assert_eq!(characters(&quarter), "▚▟\n▖ \n");
+
+        let braille = render(&shades(&rows), 4, 4, &palette, Glyphs::Braille);
+        assert_eq!(characters(&braille), "\u{2851}\u{281a}\n");
+        // One color change per line
+        assert_eq!(braille.matches("\x1b[38").count(), 1);
+    }
+
+    #[test]
+    fn glyphs_by_name() {
+        assert_eq!(Glyphs::from_name("half"), Some(Glyphs::HalfBlocks));
+        assert_eq!(Glyphs::from_name("braille"), Some(Glyphs::Braille));
+        assert_eq!(Glyphs::from_name("sextant"), None);
+    }
+
+    #[test]
+    fn keys_are_held() {
+        assert_eq!(key_index('a'), Some(10));
+        assert_eq!(key_index('7'), Some(7));
+        assert_eq!(key_index('g'), None);
+
+        let mut keys = Keys::new(false);
+        keys.press(5, 100);
+        assert!(keys.key_pad(100 + HOLD_FRAMES - 1)[5]);
+        assert!(!keys.key_pad(100 + HOLD_FRAMES)[5]);
+
+        let mut keys = Keys::new(true);
+        keys.press(5, 100);
+        assert!(keys.key_pad(1000)[5]);
+        keys.release(5);
+        assert!(!keys.key_pad(1000)[5]);
+    }
+}
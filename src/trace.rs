@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::disasm;
+use crate::instruction::Instruction;
+
+/// Which steps end up in the trace. Every set filter must match.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    /// Inclusive PC range
+    pub pc: Option<(u16, u16)>,
+    /// Opcode classes, i.e. the top nibble of the opcode
+    pub classes: Option<Vec<u8>>,
+    /// Inclusive frame range
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, frame: u64, pc: u16, opcode: u16) -> bool {
+        if let Some((start, end)) = self.pc {
+            if pc < start || pc > end {
+                return false;
+            }
+        }
+
+        if let Some(classes) = &self.classes {
+            if !classes.contains(&((opcode >> 12) as u8)) {
+                return false;
+            }
+        }
+
+        if let Some((start, end)) = self.frames {
+            if frame < start || frame > end {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Machine state right before an instruction executes.
+pub struct TraceStep<'a> {
+    pub frame: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    /// Operand of the 4-byte `F000 NNNN` form
+    pub long: Option<u16>,
+    pub registers: &'a [u8; 16],
+    pub ireg: u16,
+    pub delay_timer: u16,
+    pub sound_timer: u16,
+}
+
+/// One line per step, e.g.
+/// `frame=3 pc=0x0204 op=0x6111 v=00,00,... i=0x022a dt=0 st=0 asm="LD V1, 0x11"`
+pub fn format_step(step: &TraceStep) -> String {
+    let instr = Instruction::decode(step.opcode);
+    let asm = disasm::classic(&instr, step.long, &BTreeMap::new());
+    let registers: Vec<String> = step
+        .registers
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect();
+
+    format!(
+        "frame={} pc={:#06x} op={:#06x} v={} i={:#06x} dt={} st={} asm=\"{}\"",
+        step.frame,
+        step.pc,
+        step.opcode,
+        registers.join(","),
+        step.ireg,
+        step.delay_timer,
+        step.sound_timer,
+        asm
+    )
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+    /// First failed write, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Self {
+            out,
+            filter,
+            error: None,
+        }
+    }
+
+    pub fn to_file(path: &str, filter: TraceFilter) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(Box::new(BufWriter::new(file)), filter))
+    }
+
+    pub fn wants(&self, frame: u64, pc: u16, opcode: u16) -> bool {
+        self.filter.matches(frame, pc, opcode)
+    }
+
+    pub fn record(&mut self, step: &TraceStep) {
+        if self.error.is_some() {
+            return;
+        }
+        // Tracing must never take the emulator down, the error waits for
+        // `finish`
+        if let Err(err) = writeln!(self.out, "{}", format_step(step)) {
+            self.error = Some(err);
+        }
+    }
+
+    /// Writes out what is buffered. Fails with the first error of the run.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Parses `0x200-0x2ff` or a single value into an inclusive range.
+pub fn parse_range(text: &str) -> Option<(u64, u64)> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (start, end),
+        None => (text, text),
+    };
+
+    let (start, end) = (parse_int(start)?, parse_int(end)?);
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// `parse_range` for addresses, which end at 0xffff
+pub fn parse_address_range(text: &str) -> Result<(u16, u16), String> {
+    let (start, end) = parse_range(text).ok_or_else(|| format!("invalid range '{}'", text))?;
+    match (u16::try_from(start), u16::try_from(end)) {
+        (Ok(start), Ok(end)) => Ok((start, end)),
+        _ => Err(format!("address {:#x} is past 0xffff", end)),
+    }
+}
+
+/// Parses a comma separated list of opcode classes, e.g. `8,d,f`.
+pub fn parse_classes(text: &str) -> Option<Vec<u8>> {
+    text.split(',')
+        .map(|class| {
+            u8::from_str_radix(class.trim(), 16)
+                .ok()
+                .filter(|c| *c < 16)
+        })
+        .collect()
+}
+
+pub(crate) fn parse_int(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_step() {
+        let mut registers = [0; 16];
+        registers[1] = 0x11;
+        let step = TraceStep {
+            frame: 3,
+            pc: 0x204,
+            opcode: 0x6111,
+            long: None,
+            registers: &registers,
+            ireg: 0x22a,
+            delay_timer: 5,
+            sound_timer: 0,
+        };
+
+        assert_eq!(
+            format_step(&step),
+            "frame=3 pc=0x0204 op=0x6111 \
+             v=00,11,00,00,00,00,00,00,00,00,00,00,00,00,00,00 \
+             i=0x022a dt=5 st=0 asm=\"LD V1, 0x11\""
+        );
+    }
+
+    /// A file on a full disk
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finish_reports_write_errors() {
+        let registers = [0; 16];
+        let step = TraceStep {
+            frame: 0,
+            pc: 0x200,
+            opcode: 0x00e0,
+            long: None,
+            registers: &registers,
+            ireg: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+        let mut tracer = Tracer::new(Box::new(Full), TraceFilter::default());
+        tracer.record(&step);
+
+        assert_eq!(tracer.finish().unwrap_err().to_string(), "disk full");
+    }
+
+    #[test]
+    fn filters() {
+        let filter = TraceFilter {
+            pc: Some((0x200, 0x2ff)),
+            classes: Some(vec![0x8, 0xd]),
+            frames: Some((10, 20)),
+        };
+
+        assert!(filter.matches(10, 0x200, 0xd123));
+        assert!(!filter.matches(9, 0x200, 0xd123));
+        assert!(!filter.matches(10, 0x300, 0xd123));
+        assert!(!filter.matches(10, 0x200, 0x6123));
+        assert!(TraceFilter::default().matches(0, 0, 0));
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert_eq!(parse_range("0x200-0x2ff"), Some((0x200, 0x2ff)));
+        assert_eq!(parse_range("60"), Some((60, 60)));
+        assert_eq!(parse_range("20-10"), None);
+
+        assert_eq!(parse_address_range("0x200-0xffff"), Ok((0x200, 0xffff)));
+        assert_eq!(
+            parse_address_range("0x10000-0x10010"),
+            Err("address 0x10010 is past 0xffff".to_string())
+        );
+        assert_eq!(parse_classes("8,d,F"), Some(vec![8, 0xd, 0xf]));
+        assert_eq!(parse_classes("8,g"), None);
+    }
+}
@@ -0,0 +1,320 @@
+//! Data-driven instruction tests. A vector file is a JSON array of cases, each
+//! giving the machine state before one opcode runs and the state expected
+//! afterwards:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "8XY6 shifts VY into VX",
+//!     "opcode": "0x8016",
+//!     "profiles": ["chip8", "xochip"],
+//!     "initial": { "v": { "0": 0, "1": "0x05" } },
+//!     "expected": { "v": { "0": 2, "f": 1 }, "pc": "0x202" }
+//!   }
+//! ]
+//! ```
+//!
+//! Numbers are JSON integers or strings, hex with a `0x` prefix. The state
+//! fields are `v` (registers by hex digit), `i`, `pc`, `stack`, `delay`,
+//! `sound`, `memory` (byte lists by start address), `keys` (keys held down)
+//! and `pixels` (`[x, y]` pairs that are on). All of them are optional.
+//! Initial registers and memory start at zero, PC at 0x200, and the opcode
+//! is written at PC. Only the expected fields that are given are checked,
+//! except `pixels`, which has to list every pixel that is on. Cases run
+//! against every quirk profile unless `profiles` narrows them down.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::chip::Chip8;
+use crate::quirks::{self, Quirks};
+
+/// An integer written as a JSON number, a decimal string or a `0x` hex string
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Literal")]
+pub struct Num(pub u16);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Literal {
+    Int(u16),
+    Text(String),
+}
+
+impl TryFrom<Literal> for Num {
+    type Error = String;
+
+    fn try_from(literal: Literal) -> Result<Self, String> {
+        let text = match literal {
+            Literal::Int(n) => return Ok(Num(n)),
+            Literal::Text(text) => text,
+        };
+
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+        parsed
+            .map(Num)
+            .map_err(|_| format!("invalid number '{}'", text))
+    }
+}
+
+impl Num {
+    fn byte(self, what: &str) -> Result<u8, String> {
+        u8::try_from(self.0).map_err(|_| format!("{} {:#x} does not fit in a byte", what, self.0))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    pub opcode: Num,
+    /// Quirk profiles the case applies to, all of them if missing
+    #[serde(default)]
+    pub profiles: Option<Vec<String>>,
+    #[serde(default)]
+    pub initial: State,
+    pub expected: State,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct State {
+    pub v: BTreeMap<String, Num>,
+    pub i: Option<Num>,
+    pub pc: Option<Num>,
+    pub stack: Option<Vec<Num>>,
+    pub delay: Option<Num>,
+    pub sound: Option<Num>,
+    pub memory: BTreeMap<String, Vec<Num>>,
+    pub keys: Vec<Num>,
+    pub pixels: Option<Vec<(usize, usize)>>,
+}
+
+pub fn parse(json: &str) -> Result<Vec<Case>, String> {
+    serde_json::from_str(json).map_err(|e| e.to_string())
+}
+
+/// Runs every case in the file against its profiles and returns one line per
+/// failure, or the parse error.
+pub fn run_file(path: &Path) -> Vec<String> {
+    let name = path.display();
+    let cases = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| parse(&json))
+    {
+        Ok(cases) => cases,
+        Err(e) => return vec![format!("{}: {}", name, e)],
+    };
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        for profile in case.profiles() {
+            let result = match Quirks::from_name(profile) {
+                Some(quirks) => case.run(quirks),
+                None => Err(format!("unknown profile '{}'", profile)),
+            };
+
+            if let Err(e) = result {
+                failures.push(format!("{}: {} [{}]: {}", name, case.name, profile, e));
+            }
+        }
+    }
+
+    failures
+}
+
+impl Case {
+    pub fn profiles(&self) -> Vec<&str> {
+        match &self.profiles {
+            Some(profiles) => profiles.iter().map(String::as_str).collect(),
+            None => quirks::PROFILES.to_vec(),
+        }
+    }
+
+    /// Executes the opcode on a fresh machine. The error lists every field
+    /// that does not match.
+    pub fn run(&self, quirks: Quirks) -> Result<(), String> {
+        let mut chip = Chip8::from_rom(&[], quirks);
+        self.setup(&mut chip)?;
+        chip.step();
+
+        let mismatches = self.compare(&chip)?;
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches.join(", "))
+        }
+    }
+
+    fn setup(&self, chip: &mut Chip8) -> Result<(), String> {
+        let state = &self.initial;
+
+        for (reg, value) in &state.v {
+            chip.registers[register(reg)?] = value.byte("register value")?;
+        }
+        for (addr, bytes) in &state.memory {
+            let start = address(addr)?;
+            for (offset, byte) in bytes.iter().enumerate() {
+                *memory_mut(chip, start + offset)? = byte.byte("memory value")?;
+            }
+        }
+        for key in &state.keys {
+            chip.key_pad[usize::from(key.byte("key")? & 0xf)] = true;
+        }
+        for (x, y) in state.pixels.iter().flatten() {
+            chip.screen.draw_sprite(*x as u8, *y as u8, &[0x80], true);
+        }
+
+        chip.ireg = state.i.map_or(0, |i| i.0);
+        chip.ip = usize::from(state.pc.map_or(0x200, |pc| pc.0));
+        chip.stack = state.stack.iter().flatten().map(|n| n.0).collect();
+        chip.delay_timer = state.delay.map_or(0, |n| n.0);
+        chip.sound_timer = state.sound.map_or(0, |n| n.0);
+
+        let [high, low] = self.opcode.0.to_be_bytes();
+        *memory_mut(chip, chip.ip)? = high;
+        *memory_mut(chip, chip.ip + 1)? = low;
+
+        Ok(())
+    }
+
+    fn compare(&self, chip: &Chip8) -> Result<Vec<String>, String> {
+        let state = &self.expected;
+        let mut mismatches = Vec::new();
+        let mut check = |what: String, expected: u16, actual: u16| {
+            if expected != actual {
+                mismatches.push(format!(
+                    "{} is {:#x}, expected {:#x}",
+                    what, actual, expected
+                ));
+            }
+        };
+
+        for (reg, value) in &state.v {
+            let index = register(reg)?;
+            check(
+                format!("V{:X}", index),
+                value.0,
+                u16::from(chip.registers[index]),
+            );
+        }
+        for (addr, bytes) in &state.memory {
+            let start = address(addr)?;
+            for (offset, byte) in bytes.iter().enumerate() {
+                let actual = chip.memory().get(start + offset).copied().unwrap_or(0);
+                check(
+                    format!("memory[{:#x}]", start + offset),
+                    byte.0,
+                    u16::from(actual),
+                );
+            }
+        }
+        if let Some(i) = state.i {
+            check("I".to_string(), i.0, chip.ireg());
+        }
+        if let Some(pc) = state.pc {
+            check("PC".to_string(), pc.0, chip.pc());
+        }
+        if let Some(delay) = state.delay {
+            check("delay timer".to_string(), delay.0, chip.delay_timer);
+        }
+        if let Some(sound) = state.sound {
+            check("sound timer".to_string(), sound.0, chip.sound_timer);
+        }
+
+        if let Some(stack) = &state.stack {
+            let expected: Vec<u16> = stack.iter().map(|n| n.0).collect();
+            if expected != chip.stack {
+                mismatches.push(format!(
+                    "stack is {:x?}, expected {:x?}",
+                    chip.stack, expected
+                ));
+            }
+        }
+
+        if let Some(pixels) = &state.pixels {
+            let screen = &chip.screen;
+            let mut expected = pixels.clone();
+            let mut actual: Vec<(usize, usize)> = (0..screen.height)
+                .flat_map(|y| (0..screen.width).map(move |x| (x, y)))
+                .filter(|(x, y)| screen.get(*x, *y) != 0)
+                .collect();
+            expected.sort();
+            actual.sort();
+
+            if expected != actual {
+                mismatches.push(format!(
+                    "pixels on are {:?}, expected {:?}",
+                    actual, expected
+                ));
+            }
+        }
+
+        Ok(mismatches)
+    }
+}
+
+fn register(name: &str) -> Result<usize, String> {
+    match usize::from_str_radix(name, 16) {
+        Ok(index) if index < 16 => Ok(index),
+        _ => Err(format!("invalid register '{}'", name)),
+    }
+}
+
+fn address(text: &str) -> Result<usize, String> {
+    let literal = Literal::Text(text.to_string());
+    Num::try_from(literal).map(|n| usize::from(n.0))
+}
+
+fn memory_mut(chip: &mut Chip8, addr: usize) -> Result<&mut u8, String> {
+    chip.rom_bytes
+        .get_mut(addr)
+        .ok_or_else(|| format!("address {:#x} is outside memory", addr))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbers_and_defaults() {
+        let cases = parse(
+            r#"[{ "name": "ld", "opcode": "0x6A2B",
+                  "initial": { "memory": { "0x300": ["0x10", 5] } },
+                  "expected": { "v": { "a": 43 } } }]"#,
+        )
+        .unwrap();
+        let case = &cases[0];
+
+        assert_eq!(case.opcode, Num(0x6a2b));
+        assert_eq!(case.profiles(), quirks::PROFILES.to_vec());
+        assert_eq!(case.initial.memory["0x300"], vec![Num(0x10), Num(5)]);
+        assert_eq!(case.run(Quirks::default()), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let cases = parse(
+            r#"[{ "name": "ld", "opcode": "0x6A2B",
+                  "expected": { "v": { "a": 1 }, "pc": "0x204", "i": 0 } }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cases[0].run(Quirks::default()),
+            Err("VA is 0x2b, expected 0x1, PC is 0x202, expected 0x204".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_bad_numbers() {
+        assert!(parse(r#"[{ "name": "x", "opcode": 0, "expected": { "vx": {} } }]"#).is_err());
+        assert!(parse(r#"[{ "name": "x", "opcode": "0xZZ", "expected": {} }]"#).is_err());
+    }
+}
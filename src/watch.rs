@@ -0,0 +1,119 @@
+//! Developer mode: reloads the ROM when it, or the Octo source next to it,
+//! changes on disk. Files are polled, which is plenty for a handful of them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::asm;
+
+/// How often the watched file is looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The ROM in `path`, assembled first if it is Octo source
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    if is_source(path) {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let program = asm::assemble(&source).map_err(|err| err.to_string())?;
+        Ok(program.rom)
+    } else {
+        fs::read(path).map_err(|err| err.to_string())
+    }
+}
+
+fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "8o")
+}
+
+/// `game.8o` for `game.ch8`, if it exists
+pub fn source_for(rom: &Path) -> Option<PathBuf> {
+    let source = rom.with_extension("8o");
+    (!is_source(rom) && source.is_file()).then_some(source)
+}
+
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_poll: Instant,
+}
+
+impl Watcher {
+    /// Watches the source of `rom` if there is one next to it, else `rom`
+    pub fn new(rom: &Path) -> Self {
+        let path = source_for(rom).unwrap_or_else(|| rom.to_path_buf());
+
+        Self {
+            modified: modified(&path),
+            path,
+            next_poll: Instant::now() + POLL_INTERVAL,
+        }
+    }
+
+    /// The file that is watched, the one to reload from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True once after each change to the file
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + POLL_INTERVAL;
+
+        self.changed()
+    }
+
+    fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        // A file that is being saved can be missing for a moment
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn reloads_the_source_next_to_a_rom() {
+        let dir = std::env::temp_dir().join(format!("chip8-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        let source = dir.join("game.8o");
+        fs::write(&rom, [0x00, 0xe0]).unwrap();
+        assert_eq!(Watcher::new(&rom).path(), rom);
+
+        fs::write(&source, "clear\nv1 := 0x11\n").unwrap();
+        let mut watcher = Watcher::new(&rom);
+        assert_eq!(watcher.path(), source);
+        assert_eq!(read_rom(watcher.path()).unwrap(), [0x00, 0xe0, 0x61, 0x11]);
+        assert!(!watcher.changed());
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::write(&source, "v1 := ").unwrap();
+        assert!(read_rom(&source).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
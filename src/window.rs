@@ -0,0 +1,331 @@
+//! The SDL frontend: runs a ROM in a window, with the debug overlay, hotkeys,
+//! on-screen messages and reloading of dropped or changed ROMs.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::capture::{self, Capture};
+use crate::chip::{Chip8, FrameBudget, RunOptions};
+use crate::display::{Display, WINDOW_TITLE};
+use crate::input::{Input, InputAction};
+use crate::menu;
+use crate::movie::Movie;
+use crate::osd::{self, Messages, Status};
+use crate::overlay::DebugOverlay;
+use crate::palette;
+use crate::phosphor::Phosphor;
+use crate::quirks::Quirks;
+use crate::screenshot;
+use crate::watch::{self, Watcher};
+
+/// Speed steps, as multiples of the instructions per second
+const SPEEDS: [f32; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+pub struct Externals {
+    pub display: Display,
+    pub input: Input,
+}
+
+impl Default for Externals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Externals {
+    /// Opens the window
+    pub fn new() -> Self {
+        let mut display = Display::new();
+
+        Self {
+            input: Input::new(&mut display),
+            display,
+        }
+    }
+}
+
+/// Why `window_loop` returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Esc or the controller's back button
+    Back,
+    /// The window was closed
+    Closed,
+}
+
+/// Runs until the window is closed or the player leaves with Esc, and returns
+/// the machine for reporting.
+pub fn main_chip_loop(rom_name: &str, options: RunOptions) -> Chip8 {
+    window_loop(rom_name, options, Externals::new()).0
+}
+
+/// Runs `rom_name` in the window of `externals` until the player leaves. The
+/// window is handed back in the machine's `externals`. Octo source is
+/// assembled first.
+pub fn window_loop(rom_name: &str, options: RunOptions, externals: Externals) -> (Chip8, Exit) {
+    let rom = watch::read_rom(Path::new(rom_name)).expect("Error reading rom file!");
+    let mut cpu = Chip8::from_rom(&rom, options.quirks);
+    cpu.externals = Some(externals);
+    cpu.tracer = options.tracer;
+    cpu.profiler = options.profiler;
+    cpu.callgraph = options.callgraph;
+    cpu.coverage = options.coverage;
+    if let Some(seed) = options.seed {
+        cpu.set_seed(seed);
+    }
+
+    let mut instr_per_secs = options.instr_per_secs;
+    let mut replay = options.replay;
+    if let Some(movie) = &replay {
+        movie.prepare(&mut cpu);
+        instr_per_secs = movie.instr_per_secs;
+    }
+
+    if options.record {
+        cpu.recording = Some(Movie::new(&rom, cpu.seed(), cpu.quirks, instr_per_secs));
+    }
+
+    let display = display_mut(&mut cpu);
+    display.phosphor = Phosphor::new(options.persistence);
+    display.palette = options.palette.clone();
+    display.integer_scaling = options.integer_scaling;
+    display.filters = options.filters.clone();
+    display.set_rotation(options.rotation);
+    if options.fullscreen {
+        display.toggle_fullscreen();
+    }
+    let mut cli_capture = capture::start(
+        options.gif.as_deref(),
+        options.raw_frames,
+        options.palette.clone(),
+    )
+    .expect("Error creating capture files!");
+    let mut hotkey_capture: Option<Capture> = None;
+
+    let mut overlay = DebugOverlay::default();
+    let mut messages = Messages::default();
+    let mut status = Status::default();
+    let frame_time = Duration::from_secs_f32(1.0 / 60.0);
+    let mut speed = SPEEDS.iter().position(|speed| *speed == 1.0).unwrap();
+    let mut budget = FrameBudget::new(instr_per_secs);
+    let mut paused = false;
+    let mut rom_path = PathBuf::from(rom_name);
+    let mut watcher = options.watch.then(|| Watcher::new(&rom_path));
+    let mut last_fault = None;
+
+    let exit = 'mainloop: loop {
+        let frame_start = Instant::now();
+
+        match input_mut(&mut cpu).handle_input() {
+            InputAction::BreakDisplay => break 'mainloop Exit::Closed,
+            InputAction::Back => break 'mainloop Exit::Back,
+            InputAction::ToggleOverlay => {
+                overlay.toggle();
+                display_mut(&mut cpu).set_panel_visible(overlay.visible);
+            }
+            InputAction::ScrollMemory(pages) => {
+                overlay.scroll_memory(pages, cpu.ireg, cpu.memory().len())
+            }
+            InputAction::FollowIndex => overlay.follow_index(),
+            InputAction::Screenshot { native } => {
+                let png = display_mut(&mut cpu).screenshot(native);
+                match screenshot::save(&options.screenshot_dir, &png) {
+                    Ok(path) => {
+                        eprintln!("Saved screenshot {}", path.display());
+                        messages.show("Screenshot saved");
+                    }
+                    Err(err) => {
+                        eprintln!("Error saving screenshot: {}", err);
+                        messages.show("Error saving screenshot");
+                    }
+                }
+            }
+            InputAction::ToggleFullscreen => display_mut(&mut cpu).toggle_fullscreen(),
+            InputAction::CyclePalette => {
+                let display = display_mut(&mut cpu);
+                if let Some(next) = palette::next(&options.palettes, &display.palette) {
+                    display.palette = next.clone();
+                    eprintln!("Palette {}", next.name);
+                    messages.show(format!("Palette {}", next.name));
+                }
+            }
+            InputAction::ToggleCapture => match hotkey_capture.take() {
+                Some(capture) => {
+                    capture.finish().expect("Error writing capture!");
+                    eprintln!("Stopped capture");
+                    messages.show("Capture stopped");
+                }
+                None => {
+                    let path = capture::gif_path(&options.screenshot_dir);
+                    let palette = display_mut(&mut cpu).palette.clone();
+                    match Capture::new(palette).gif(&path) {
+                        Ok(capture) => {
+                            eprintln!("Capturing to {}", path.display());
+                            messages.show("Capturing");
+                            hotkey_capture = Some(capture);
+                        }
+                        Err(err) => {
+                            eprintln!("Error starting capture: {}", err);
+                            messages.show("Error starting capture");
+                        }
+                    }
+                }
+            },
+            InputAction::LoadRom(path) => match watch::read_rom(&path) {
+                Ok(rom) => {
+                    let platform = menu::platform_for_extension(
+                        &path.extension().unwrap_or_default().to_string_lossy(),
+                    );
+                    cpu.quirks = Quirks::from_name(platform).unwrap_or_default();
+                    restart(&mut cpu, &rom, instr_per_secs);
+                    replay = None;
+                    if options.watch {
+                        watcher = Some(Watcher::new(&path));
+                    }
+                    eprintln!("Loaded {}", path.display());
+                    messages.show(format!("Loaded {}", file_name(&path)));
+                    rom_path = path;
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", path.display(), err);
+                    messages.show(err);
+                }
+            },
+            InputAction::TogglePause => {
+                paused = !paused;
+                messages.show(if paused { "Paused" } else { "Resumed" });
+            }
+            // A movie only replays at the speed it was recorded at
+            InputAction::ChangeSpeed(_) if cpu.recording.is_some() || replay.is_some() => {
+                messages.show("Speed is fixed with movies");
+            }
+            InputAction::ChangeSpeed(steps) => {
+                speed = speed
+                    .saturating_add_signed(steps as isize)
+                    .min(SPEEDS.len() - 1);
+                budget = FrameBudget::new(instr_per_secs * SPEEDS[speed]);
+                messages.show(format!("Speed {}x", SPEEDS[speed]));
+            }
+            InputAction::None => {}
+        }
+
+        if let Some(watcher) = watcher.as_mut() {
+            if watcher.poll() {
+                match watch::read_rom(watcher.path()) {
+                    Ok(rom) => {
+                        restart(&mut cpu, &rom, instr_per_secs);
+                        replay = None;
+                        eprintln!("Reloaded {}", watcher.path().display());
+                        messages.show(format!("Reloaded {}", file_name(watcher.path())));
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", watcher.path().display(), err);
+                        messages.show(err);
+                    }
+                }
+            }
+        }
+
+        let mut executed = 0;
+        if !paused {
+            let frame = cpu.frame as usize;
+            cpu.key_pad = match replay.as_ref().and_then(|movie| movie.key_pad(frame)) {
+                Some(key_pad) => key_pad,
+                None => input_mut(&mut cpu).key_pad,
+            };
+            if let Some(movie) = cpu.recording.as_mut() {
+                movie.record(&cpu.key_pad);
+            }
+
+            executed = cpu.run_frame(budget.take_frame());
+            for capture in [cli_capture.as_mut(), hotkey_capture.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                capture
+                    .frame(&cpu.screen, cpu.sound_timer > 0)
+                    .expect("Error writing capture!");
+            }
+
+            if let Some(movie) = replay.as_ref().filter(|m| m.frames.len() == frame + 1) {
+                if movie.matches_end_state(&cpu) {
+                    eprintln!("Replay finished in the recorded state");
+                    messages.show("Replay finished");
+                } else {
+                    eprintln!("Replay finished, but the state differs from the recording");
+                    messages.show("Replay finished in a different state");
+                }
+            }
+        }
+
+        if cpu.fault != last_fault {
+            if let Some(fault) = cpu.fault {
+                eprintln!("{}", fault);
+                messages.show(fault.to_string());
+            }
+            last_fault = cpu.fault;
+        }
+
+        render(&mut cpu, &overlay, &messages);
+        messages.tick();
+        if status.frame(executed) {
+            let profile = cpu.quirks.profile().unwrap_or("custom");
+            let title = osd::title(&file_name(&rom_path), profile, &status);
+            display_mut(&mut cpu).set_title(&title);
+        }
+
+        if let Some(rest) = frame_time.checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(rest);
+        }
+    };
+
+    for capture in [cli_capture, hotkey_capture].into_iter().flatten() {
+        capture.finish().expect("Error writing capture!");
+    }
+    let display = display_mut(&mut cpu);
+    display.set_panel_visible(false);
+    display.set_title(WINDOW_TITLE);
+
+    (cpu, exit)
+}
+
+/// `pong.ch8` for `roms/pong.ch8`
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Resets `cpu` with `rom`, starting a new movie if one was being recorded
+fn restart(cpu: &mut Chip8, rom: &[u8], instr_per_secs: f32) {
+    let recording = cpu.recording.is_some();
+    cpu.reset(rom);
+    if recording {
+        cpu.recording = Some(Movie::new(rom, cpu.seed(), cpu.quirks, instr_per_secs));
+    }
+}
+
+/// Draws the screen and, if visible, the debug overlay
+fn render(cpu: &mut Chip8, overlay: &DebugOverlay, messages: &Messages) {
+    let Some(mut externals) = cpu.externals.take() else {
+        return;
+    };
+
+    externals.display.render_screen(&cpu.screen);
+    messages.draw(&mut externals.display);
+    if overlay.visible {
+        overlay.draw(&mut externals.display, cpu);
+    }
+    externals.display.present();
+
+    cpu.externals = Some(externals);
+}
+
+fn display_mut(cpu: &mut Chip8) -> &mut Display {
+    &mut cpu.externals.as_mut().expect("The window is gone!").display
+}
+
+fn input_mut(cpu: &mut Chip8) -> &mut Input {
+    &mut cpu.externals.as_mut().expect("The window is gone!").input
+}
@@ -0,0 +1,39 @@
+//! Replays the inputs in `fuzz/regressions`, which once crashed the fuzz
+//! targets of the same name.
+
+use std::fs;
+use std::panic;
+
+use cpu_emulator::fuzz;
+
+fn replay(target: &str, harness: fn(&[u8])) {
+    let mut paths: Vec<_> = fs::read_dir(format!("fuzz/regressions/{}", target))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter(|path| {
+            let data = fs::read(path).unwrap();
+            panic::catch_unwind(|| harness(&data)).is_err()
+        })
+        .map(|path| path.display().to_string())
+        .collect();
+
+    assert!(failures.is_empty(), "crashed: {}", failures.join(", "));
+}
+
+#[test]
+fn interpreter() {
+    replay("interpreter", |data| {
+        fuzz::run(data);
+    });
+}
+
+#[test]
+fn decoder() {
+    replay("decoder", fuzz::decode);
+}
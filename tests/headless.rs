@@ -0,0 +1,27 @@
+//! Runs the binary headlessly, the way batch jobs in CI do.
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn headless_trace_reaches_the_file() {
+    let dir = std::env::temp_dir().join(format!("chip8-headless-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let trace = dir.join("trace.log");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cpu-emulator"))
+        .args(["run", "roms/2-ibm-logo.ch8", "--headless", "--frames", "30"])
+        .arg("--trace")
+        .arg(&trace)
+        .arg("--out")
+        .arg(dir.join("screen.txt"))
+        .arg("--summary")
+        .arg(dir.join("summary.json"))
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let text = fs::read_to_string(&trace).unwrap();
+    assert!(text.starts_with("frame=0 pc=0x0200 op=0x00e0"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
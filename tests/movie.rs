@@ -0,0 +1,68 @@
+//! Records headless runs into movies and checks that replaying them ends in
+//! exactly the same state.
+
+use std::fs;
+
+use cpu_emulator::chip::{Chip8, FrameBudget};
+use cpu_emulator::movie::{self, Movie};
+use cpu_emulator::quirks::Quirks;
+
+const SPEED: f32 = 700.0;
+
+/// Runs `rom` for `frames` frames, pressing keys from `script`, and records it
+fn record(rom: &[u8], seed: u64, frames: usize, script: impl Fn(usize) -> u16) -> (Chip8, Movie) {
+    let mut chip = Chip8::from_rom(rom, Quirks::chip8());
+    chip.set_seed(seed);
+    let mut movie = Movie::new(rom, seed, chip.quirks, SPEED);
+    let mut budget = FrameBudget::new(SPEED);
+
+    for frame in 0..frames {
+        chip.key_pad = movie::key_pad(script(frame));
+        movie.record(&chip.key_pad);
+        chip.run_frame(budget.take_frame());
+    }
+    movie.finish(&chip);
+
+    (chip, movie)
+}
+
+fn replay(rom: &[u8], movie: &Movie) -> Chip8 {
+    let movie = Movie::parse(&movie.format()).unwrap();
+    let mut chip = Chip8::from_rom(rom, Quirks::default());
+    movie.play(&mut chip);
+    chip
+}
+
+#[test]
+fn replay_reproduces_menu_navigation() {
+    let rom = fs::read("roms/5-quirks.ch8").unwrap();
+    // Pick the CHIP-8 tests from the menu
+    let (recorded, movie) = record(&rom, 1, 600, |frame| {
+        if (200..220).contains(&frame) {
+            1 << 1
+        } else {
+            0
+        }
+    });
+
+    let replayed = replay(&rom, &movie);
+
+    assert!(movie.matches_rom(&rom));
+    assert!(movie.matches_end_state(&replayed));
+    assert_eq!(replayed.screen, recorded.screen);
+    assert_eq!(replayed.frame, 600);
+}
+
+#[test]
+fn replay_reproduces_random_numbers() {
+    // loop: V0 := random 0xff, I := 0x300 + V0, save V0, jump loop
+    let rom = [0xc0, 0xff, 0xa3, 0x00, 0xf0, 0x1e, 0xf0, 0x55, 0x12, 0x00];
+
+    let (recorded, movie) = record(&rom, 7, 30, |_| 0);
+    let replayed = replay(&rom, &movie);
+    assert_eq!(replayed.state_hash(), recorded.state_hash());
+    assert_eq!(replayed.memory(), recorded.memory());
+
+    let (other_seed, _) = record(&rom, 8, 30, |_| 0);
+    assert_ne!(other_seed.memory(), recorded.memory());
+}
@@ -0,0 +1,99 @@
+//! Runs the Timendus test suite in `roms/` headlessly and compares the final
+//! screen against the goldens in `tests/golden`, one per quirk profile.
+
+use std::fs;
+
+use cpu_emulator::chip::Chip8;
+use cpu_emulator::quirks::{self, Quirks};
+use cpu_emulator::snapshot::{self, Press, Snapshots};
+
+const INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+fn check(rom: &str, name: &str, profile: &str, frames: u64, presses: &[Press]) {
+    let quirks = Quirks::from_name(profile).unwrap();
+    let mut chip = Chip8::from_rom(&fs::read(format!("roms/{}.ch8", rom)).unwrap(), quirks);
+    snapshot::run_until(&mut chip, frames, INSTRUCTIONS_PER_FRAME, presses);
+
+    Snapshots::new("tests/golden").assert(&format!("{}.{}", name, profile), &chip.screen);
+}
+
+fn check_all_profiles(rom: &str, frames: u64) {
+    for profile in quirks::PROFILES {
+        check(rom, rom, profile, frames, &[]);
+    }
+}
+
+#[test]
+fn chip8_logo() {
+    check_all_profiles("1-chip8-logo", 60);
+}
+
+#[test]
+fn ibm_logo() {
+    check_all_profiles("2-ibm-logo", 60);
+}
+
+#[test]
+fn corax_plus() {
+    check_all_profiles("3-corax+", 120);
+}
+
+#[test]
+fn flags() {
+    check_all_profiles("4-flags", 120);
+}
+
+#[test]
+fn quirks() {
+    // The menu picks the platform to test against, which has to match the profile
+    for (key, profile) in quirks::PROFILES.iter().enumerate() {
+        let select = Press {
+            frame: 200,
+            key: key + 1,
+            frames: 20,
+        };
+        check("5-quirks", "5-quirks", profile, 3000, &[select]);
+    }
+}
+
+#[test]
+fn keypad_fx0a() {
+    // Test 3 passes once a key is pressed and released
+    let presses = [
+        Press {
+            frame: 100,
+            key: 3,
+            frames: 20,
+        },
+        Press {
+            frame: 200,
+            key: 5,
+            frames: 5,
+        },
+    ];
+
+    for profile in quirks::PROFILES {
+        check("6-keypad", "6-keypad-fx0a", profile, 400, &presses);
+    }
+}
+
+#[test]
+fn keypad_ex9e() {
+    // Test 1 highlights the keys that are held down
+    let presses = [
+        Press {
+            frame: 100,
+            key: 1,
+            frames: 20,
+        },
+        Press {
+            frame: 200,
+            key: 5,
+            frames: 200,
+        },
+    ];
+
+    for profile in quirks::PROFILES {
+        check("6-keypad", "6-keypad-ex9e", profile, 400, &presses);
+    }
+}
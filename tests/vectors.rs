@@ -0,0 +1,23 @@
+//! Runs the per-instruction JSON vectors in `tests/vectors`.
+
+use std::fs;
+
+use cpu_emulator::vectors;
+
+#[test]
+fn instruction_vectors() {
+    let mut paths: Vec<_> = fs::read_dir("tests/vectors")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .flat_map(|path| vectors::run_file(path))
+        .collect();
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}